@@ -1,4 +1,8 @@
-use std::{ffi::CString, fs, io::BufWriter};
+use std::{
+    ffi::CString,
+    fs,
+    io::{BufWriter, Cursor},
+};
 
 use cqdb::{CQDB, CQDBWriter};
 use criterion::{Criterion, criterion_group, criterion_main};
@@ -44,6 +48,36 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
     group.finish();
 
+    let mut group = c.benchmark_group("to_ids");
+    // Large enough that buckets and records do not fit in the CPU caches
+    let keys: Vec<String> = (0..2_000_000).map(|i| format!("w[-1]={:08}", i)).collect();
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap();
+    for (id, key) in keys.iter().enumerate() {
+        writer.put(key, id as u32).unwrap();
+    }
+    drop(writer);
+    let buf = buf.into_inner();
+    let db = CQDB::new(&buf).unwrap();
+    // Scatter queries over the whole database
+    let queries: Vec<&str> = (0..4096)
+        .map(|i| keys[(i * 7919 * 131) % keys.len()].as_str())
+        .collect();
+    let mut ids = vec![None; queries.len()];
+    group.bench_function("to_id-loop", |b| {
+        b.iter(|| {
+            for (query, id) in queries.iter().zip(ids.iter_mut()) {
+                *id = db.to_id(query);
+            }
+        })
+    });
+    group.bench_function("to_ids-batch", |b| {
+        b.iter(|| {
+            db.to_ids(&queries, &mut ids);
+        })
+    });
+    group.finish();
+
     let mut group = c.benchmark_group("to_string");
     group.bench_function("cqdb-rs", |b| {
         let buf = fs::read("tests/fixtures/test.cqdb").unwrap();
@@ -91,8 +91,8 @@ ffi_fn! {
     fn cqdb_to_id(db: *mut cqdb_t, s: *const c_char) -> c_int {
         let db = db as *mut CQDB;
         unsafe {
-            let c_str = CStr::from_ptr(s).to_str().unwrap();
-            (*db).to_id(c_str).map(|id| id as c_int).unwrap_or(CQDB_ERROR_NOTFOUND)
+            let key = CStr::from_ptr(s).to_bytes();
+            (*db).to_id(key).map(|id| id as c_int).unwrap_or(CQDB_ERROR_NOTFOUND)
         }
     }
 }
@@ -178,11 +178,14 @@ ffi_fn! {
     /// If an unexpected error occurs, this function tries to rewind the stream position to
     /// the original position when the function `cqdb_writer()` was called.
     fn cqdb_writer_close(dbw: *mut cqdb_writer_t) -> c_int {
+        let mut ret = CQDB_SUCCESS;
         if !dbw.is_null() {
             unsafe {
                 let inner = (*dbw).inner as *mut CQDBWriter<BufWriter<File>>;
-                // Drop CQDBWriter
-                drop(Box::from_raw(inner));
+                // Finish CQDBWriter
+                if Box::from_raw(inner).finish().is_err() {
+                    ret = CQDB_ERROR_FILEWRITE;
+                }
                 // Re-sync file position so that ftell works correctly
                 // Reference: https://stackoverflow.com/a/31688641
                 let offset = libc::lseek(libc::fileno((*dbw).file), 0, libc::SEEK_CUR);
@@ -190,7 +193,7 @@ ffi_fn! {
                 drop(Box::from_raw(dbw));
             }
         }
-        CQDB_SUCCESS
+        ret
     }
 }
 
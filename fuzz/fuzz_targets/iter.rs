@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(db) = cqdb::CQDB::new(data) {
+        let _ = db.iter().count();
+        let _ = db.iter_all().rev().count();
+        let _ = db.records().count();
+        if let Some(iter) = db.range::<&[u8], _>(..) {
+            let _ = iter.count();
+        }
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(db) = cqdb::CQDB::new(data) {
+        let _ = db.num();
+        let _ = db.stats();
+        let _ = db.verify();
+    }
+    let _ = cqdb::CQDB::chunks(data).count();
+});
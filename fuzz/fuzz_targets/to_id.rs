@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The first byte is the key length, then the key, then the database
+fuzz_target!(|data: &[u8]| {
+    let Some((&len, rest)) = data.split_first() else {
+        return;
+    };
+    let (key, buf) = rest.split_at((len as usize).min(rest.len()));
+    if let Ok(db) = cqdb::CQDB::new(buf) {
+        let _ = db.try_to_id(key);
+        let _ = db.candidates(cqdb::KeyHash::new(key)).count();
+        let mut ids = [None];
+        db.to_ids(&[key], &mut ids);
+    }
+    if let Ok(mut db) = cqdb::LazyCQDB::new(std::io::Cursor::new(buf)) {
+        let _ = db.try_to_id(key);
+    }
+});
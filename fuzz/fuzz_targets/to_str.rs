@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The first four bytes are the id, then the database
+fuzz_target!(|data: &[u8]| {
+    let Some((id, buf)) = data.split_first_chunk::<4>() else {
+        return;
+    };
+    let id = u32::from_le_bytes(*id);
+    if let Ok(db) = cqdb::CQDB::new(buf) {
+        let _ = db.try_to_str(id);
+    }
+    if let Ok(mut db) = cqdb::LazyCQDB::new(std::io::Cursor::new(buf)) {
+        let _ = db.try_to_str(id);
+    }
+});
@@ -0,0 +1,101 @@
+//! Batched forward lookups with software prefetching
+use crate::{KeyHash, Layout};
+
+/// Number of lookups kept in flight at once
+const BATCH_SIZE: usize = 16;
+
+/// Hint the CPU to fetch the cache line at `buffer[offset]`.
+///
+/// Prefetch instructions never fault, so `offset` does not need to be in bounds.
+#[inline(always)]
+fn prefetch(buffer: &[u8], offset: usize) {
+    let ptr = buffer.as_ptr().wrapping_add(offset);
+    #[cfg(target_arch = "x86_64")]
+    {
+        use core::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+        // Safety: SSE is part of the x86_64 baseline and prefetching has no side effects
+        unsafe { _mm_prefetch::<_MM_HINT_T0>(ptr as *const i8) };
+    }
+    #[cfg(all(target_arch = "x86", target_feature = "sse"))]
+    {
+        use core::arch::x86::{_MM_HINT_T0, _mm_prefetch};
+        // Safety: SSE is enabled for the target and prefetching has no side effects
+        unsafe { _mm_prefetch::<_MM_HINT_T0>(ptr as *const i8) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // Safety: PRFM only hints the memory system, it never faults or writes
+        unsafe {
+            core::arch::asm!(
+                "prfm pldl1keep, [{ptr}]",
+                ptr = in(reg) ptr,
+                options(nostack, preserves_flags, readonly)
+            )
+        };
+    }
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        all(target_arch = "x86", target_feature = "sse"),
+        target_arch = "aarch64"
+    )))]
+    {
+        let _ = ptr;
+    }
+}
+
+impl Layout {
+    /// Position of the first bucket probed for `hash`, `None` if its table is empty
+    #[inline]
+    fn first_bucket(&self, hash: KeyHash) -> Option<usize> {
+        let table = &self.tables[hash.table()];
+        if table.num == 0 {
+            return None;
+        }
+        Some(table.offset + hash.bucket(table.num) as usize * self.format.bucket_size())
+    }
+
+    pub(crate) fn to_ids<K: AsRef<[u8]>>(
+        &self,
+        buffer: &[u8],
+        keys: &[K],
+        ids: &mut [Option<u32>],
+    ) {
+        #[cfg(feature = "alloc")]
+        if self.normalizer.is_some() {
+            // Normalized keys are owned, look them up one at a time
+            for (key, id) in keys.iter().zip(ids.iter_mut()) {
+                let key = self.normalize(key.as_ref());
+                *id = self.try_to_id(buffer, &key).ok().flatten();
+            }
+            return;
+        }
+        let mut hashes = [KeyHash::from_raw(0); BATCH_SIZE];
+        for (keys, ids) in keys.chunks(BATCH_SIZE).zip(ids.chunks_mut(BATCH_SIZE)) {
+            let hashes = &mut hashes[..keys.len()];
+            // Hash every key and prefetch the first bucket it probes
+            for (key, hash) in keys.iter().zip(hashes.iter_mut()) {
+                *hash = KeyHash::new(key.as_ref());
+                if let Some(pos) = self.first_bucket(*hash) {
+                    prefetch(buffer, pos);
+                }
+            }
+            // Prefetch the record the first bucket points at
+            for hash in hashes.iter() {
+                if let Some(pos) = self.first_bucket(*hash) {
+                    // Bucket read is safe: table bounds validated in new()
+                    let offset = self.format.read_offset(self.order, buffer, pos + 4);
+                    if offset > 0 {
+                        prefetch(buffer, offset as usize);
+                    }
+                }
+            }
+            // Probe and compare keys, the first bucket and record are cached by now
+            for ((key, hash), id) in keys.iter().zip(hashes.iter()).zip(ids.iter_mut()) {
+                *id = self
+                    .try_to_id_hashed(buffer, key.as_ref(), *hash)
+                    .ok()
+                    .flatten();
+            }
+        }
+    }
+}
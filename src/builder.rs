@@ -0,0 +1,208 @@
+//! In-memory builder assigning ids to keys
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt;
+
+use crate::{BufSink, ByteOrder, CQDBWriter, Error, Flag, Format, KeyNormalizer, Sink};
+
+/// Builder interning keys into dense ids, starting from 0 in first-seen order
+///
+/// This is the quark operation: [`CQDBBuilder::intern`] returns the id of a key,
+/// assigning the next free one if the key is new. The database written by
+/// [`CQDBBuilder::write_to`] or [`CQDBBuilder::to_vec`] maps every key to the
+/// same id. Unless a format is set with [`CQDBBuilder::with_format`], the
+/// database is written in [`Format::Wide`] only if it does not fit in 4 GiB.
+///
+/// ```
+/// use cqdb::{CQDB, CQDBBuilder};
+///
+/// let mut builder: CQDBBuilder = ["foo", "bar"].into_iter().collect();
+/// assert_eq!(builder.intern("baz"), 2);
+/// assert_eq!(builder.intern("foo"), 0);
+/// let buf = builder.to_vec().unwrap();
+/// let db = CQDB::new(&buf).unwrap();
+/// assert_eq!(db.to_id("bar"), Some(1));
+/// ```
+pub struct CQDBBuilder {
+    /// Id of every key
+    ids: BTreeMap<Box<[u8]>, u32>,
+    flag: Flag,
+    order: ByteOrder,
+    /// Format to write, picked by size if `None`
+    format: Option<Format>,
+    normalizer: Option<Arc<dyn KeyNormalizer>>,
+}
+
+impl fmt::Debug for CQDBBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CQDBBuilder")
+            .field("len", &self.ids.len())
+            .field("flag", &self.flag)
+            .field("order", &self.order)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl Default for CQDBBuilder {
+    fn default() -> Self {
+        Self {
+            ids: BTreeMap::new(),
+            flag: Flag::NONE,
+            order: ByteOrder::LittleEndian,
+            format: None,
+            normalizer: None,
+        }
+    }
+}
+
+impl CQDBBuilder {
+    /// Create an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write the database with `flag`
+    pub fn with_flag(mut self, flag: Flag) -> Self {
+        self.flag = flag;
+        self
+    }
+
+    /// Write integers in `order`
+    pub fn with_byte_order(mut self, order: ByteOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Write the database in `format` instead of picking it by size
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Normalize keys with `normalizer` before interning them, see [`CQDBWriter::with_normalizer`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBBuilder::intern`].
+    pub fn with_normalizer<N: KeyNormalizer + 'static>(mut self, normalizer: N) -> Self {
+        assert!(
+            self.ids.is_empty(),
+            "the normalizer must be set before interning keys"
+        );
+        self.normalizer = Some(Arc::new(normalizer));
+        self
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns `true` if no key was interned
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The id of `key`, assigning the next free id if it is new
+    ///
+    /// # Panics
+    ///
+    /// Panics if all `u32` ids are taken.
+    pub fn intern<K: AsRef<[u8]>>(&mut self, key: K) -> u32 {
+        let key = match &self.normalizer {
+            Some(normalizer) => normalizer.normalize(key.as_ref()),
+            None => key.as_ref().into(),
+        };
+        if let Some(&id) = self.ids.get(&*key) {
+            return id;
+        }
+        let id = u32::try_from(self.ids.len()).expect("too many keys");
+        self.ids.insert(key.into_owned().into_boxed_slice(), id);
+        id
+    }
+
+    /// The id of `key`, if it was interned
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<u32> {
+        let key = match &self.normalizer {
+            Some(normalizer) => normalizer.normalize(key.as_ref()),
+            None => key.as_ref().into(),
+        };
+        self.ids.get(&*key).copied()
+    }
+
+    /// Write the database to `writer`, returning it after [`CQDBWriter::finish`]
+    pub fn write_to<T: Sink>(&self, writer: T) -> Result<T, Error> {
+        let mut db = CQDBWriter::with_byte_order(writer, self.flag, self.order)?;
+        if let Some(normalizer) = &self.normalizer {
+            db.set_normalizer(Arc::clone(normalizer));
+        }
+        let format = self
+            .format
+            .unwrap_or_else(|| self.pick_format(&db, Format::Standard.max_offset()));
+        db.set_format(format)?;
+        // Write in id order, as a writer fed the keys one by one would
+        let mut keys: Vec<_> = self.ids.iter().map(|(key, &id)| (id, key)).collect();
+        keys.sort_unstable_by_key(|&(id, _)| id);
+        for (id, key) in keys {
+            // Keys were normalized by intern
+            db.put_normalized(key, id)?;
+        }
+        db.finish()
+    }
+
+    /// [`Format::Wide`] if the standard database `db` would write is larger than `limit`
+    fn pick_format<T: Sink>(&self, db: &CQDBWriter<T>, limit: u64) -> Format {
+        // Size of the standard database: one record per key, ids are dense
+        let records: u64 = self.ids.keys().map(|key| 8 + key.len() as u64 + 1).sum();
+        let num = self.ids.len() as u64;
+        if db.total_size(db.current + records, num, num) > limit {
+            Format::Wide
+        } else {
+            Format::Standard
+        }
+    }
+
+    /// Write the database into a new buffer
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.write_to(BufSink::default())?.into_inner())
+    }
+}
+
+impl<K: AsRef<[u8]>> Extend<K> for CQDBBuilder {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        for key in iter {
+            self.intern(key);
+        }
+    }
+}
+
+impl<K: AsRef<[u8]>> FromIterator<K> for CQDBBuilder {
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        let mut builder = Self::new();
+        builder.extend(iter);
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, vec::Vec};
+
+    use super::CQDBBuilder;
+    use crate::{AsciiLowercase, BufSink, CQDBWriter, Flag, Format};
+
+    #[test]
+    fn test_pick_format_by_size() {
+        let mut builder = CQDBBuilder::new()
+            .with_flag(Flag::SORTED_INDEX)
+            .with_normalizer(AsciiLowercase);
+        builder.extend((0..100).map(|i| format!("KEY{:08}", i)));
+        // The estimate is exact, so the limit is the size of the standard database
+        let size = builder.to_vec().unwrap().len() as u64;
+        let mut db =
+            CQDBWriter::with_flag(BufSink::<Vec<u8>>::default(), Flag::SORTED_INDEX).unwrap();
+        db.set_normalizer(builder.normalizer.clone().unwrap());
+        assert_eq!(builder.pick_format(&db, size), Format::Standard);
+        assert_eq!(builder.pick_format(&db, size - 1), Format::Wide);
+    }
+}
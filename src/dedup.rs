@@ -0,0 +1,117 @@
+//! Duplicate key and id detection for the writer
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use bstr::BString;
+
+use crate::Error;
+
+/// How a [`CQDBWriter`](crate::CQDBWriter) handles a key or id that was already put
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DuplicatePolicy {
+    /// Store every association, the default. Only the first of duplicate keys is
+    /// found by lookups, and the last of duplicate ids by reverse lookups.
+    #[default]
+    Allow,
+    /// Reject the association with [`Error::DuplicateKey`] or [`Error::DuplicateId`]
+    Error,
+    /// Keep the earlier association and ignore the later one
+    KeepFirst,
+    /// Replace the earlier association with the later one
+    ///
+    /// Associations are buffered in memory and written when the writer is closed.
+    KeepLast,
+}
+
+/// What the writer does with an association after the duplicate check
+pub(crate) enum Action {
+    /// Write the record now
+    Write,
+    /// Nothing to write now
+    Skip,
+}
+
+/// Keys and ids seen by the writer
+#[derive(Debug)]
+pub(crate) struct Dedup {
+    policy: DuplicatePolicy,
+    /// Index into `entries` of every key
+    keys: BTreeMap<Box<[u8]>, usize>,
+    /// Index into `entries` of every id
+    ids: BTreeMap<u32, usize>,
+    /// Associations in put order, `None` once replaced. Only kept for [`DuplicatePolicy::KeepLast`]
+    entries: Vec<Option<u32>>,
+}
+
+impl Dedup {
+    pub(crate) fn new(policy: DuplicatePolicy) -> Self {
+        Self {
+            policy,
+            keys: BTreeMap::new(),
+            ids: BTreeMap::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if no association was checked yet
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.ids.is_empty()
+    }
+
+    /// Check an association against the ones put before
+    pub(crate) fn check(&mut self, key: &[u8], id: u32) -> Result<Action, Error> {
+        let index = self.entries.len();
+        match self.policy {
+            DuplicatePolicy::Allow => Ok(Action::Write),
+            DuplicatePolicy::Error | DuplicatePolicy::KeepFirst => {
+                let duplicate = if self.keys.contains_key(key) {
+                    Some(Error::DuplicateKey {
+                        key: BString::from(key),
+                    })
+                } else if self.ids.contains_key(&id) {
+                    Some(Error::DuplicateId { id })
+                } else {
+                    None
+                };
+                match duplicate {
+                    Some(err) if self.policy == DuplicatePolicy::Error => Err(err),
+                    Some(_) => Ok(Action::Skip),
+                    None => {
+                        self.keys.insert(key.into(), index);
+                        self.ids.insert(id, index);
+                        Ok(Action::Write)
+                    }
+                }
+            }
+            DuplicatePolicy::KeepLast => {
+                // Drop the associations this one replaces. A key left pointing
+                // at a dropped association is stale and skipped at close.
+                if let Some(old) = self.keys.insert(key.into(), index)
+                    && let Some(old_id) = self.entries[old].take()
+                {
+                    self.ids.remove(&old_id);
+                }
+                if let Some(old) = self.ids.insert(id, index) {
+                    self.entries[old] = None;
+                }
+                self.entries.push(Some(id));
+                Ok(Action::Skip)
+            }
+        }
+    }
+
+    /// The buffered associations to write at close, in put order
+    pub(crate) fn take_pending(&mut self) -> Vec<(Box<[u8]>, u32)> {
+        if self.policy != DuplicatePolicy::KeepLast {
+            return Vec::new();
+        }
+        let keys = core::mem::take(&mut self.keys);
+        let mut pending: Vec<_> = keys
+            .into_iter()
+            .filter_map(|(key, index)| Some((index, key, self.entries[index]?)))
+            .collect();
+        pending.sort_unstable_by_key(|&(index, _, _)| index);
+        self.ids.clear();
+        self.entries.clear();
+        pending.into_iter().map(|(_, key, id)| (key, id)).collect()
+    }
+}
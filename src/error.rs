@@ -0,0 +1,149 @@
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+
+#[cfg(feature = "alloc")]
+use bstr::{BString, ByteSlice};
+use core::{error, fmt};
+#[cfg(feature = "std")]
+use std::io;
+
+/// Error type of CQDB operations
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The buffer is smaller than the chunk header and table references
+    TooSmall,
+    /// The chunk identifier is neither `CQDB` nor `CQ64`
+    BadMagic,
+    /// The byte-order indicator does not match
+    ByteOrder,
+    /// The chunk size is smaller than the header or exceeds the buffer
+    BadChunkSize {
+        /// Chunk size stored in the header
+        size: u64,
+    },
+    /// A hash table does not fit within the buffer
+    TableOutOfBounds {
+        /// Index of the hash table
+        table: usize,
+    },
+    /// The backward link array does not fit within the buffer
+    BackwardLinkOutOfBounds,
+    /// A record is out of bounds or malformed
+    CorruptRecord {
+        /// Offset of the record in the buffer
+        offset: usize,
+    },
+    /// The database was written with a key normalizer that is not available
+    #[cfg(feature = "alloc")]
+    UnknownNormalizer {
+        /// Name of the normalizer recorded in the database
+        name: String,
+    },
+    /// The database is flagged as normalized but its normalizer section is missing or
+    /// truncated, or normalizers are unavailable because the `alloc` feature is disabled
+    MissingNormalizer,
+    /// A key was put to a writer that rejects duplicates
+    #[cfg(feature = "alloc")]
+    DuplicateKey {
+        /// The duplicate key, after normalization
+        key: BString,
+    },
+    /// An id was put to a writer that rejects duplicates
+    DuplicateId {
+        /// The duplicate id
+        id: u32,
+    },
+    /// A key is longer than the 32-bit key size of a record can express
+    KeyTooLong {
+        /// Length of the key in bytes
+        len: usize,
+    },
+    /// The database would exceed the size its format can address, 4 GiB for
+    /// [`Format::Standard`](crate::Format::Standard)
+    TooLarge {
+        /// Size the database would need in bytes
+        size: u64,
+    },
+    /// An id is too large for the backward link array, whose size is 32-bit
+    IdTooLarge {
+        /// The id
+        id: u32,
+    },
+    /// The database would hold more records than the 32-bit record and
+    /// bucket counts can express
+    TooManyRecords,
+    /// A fixed-size output buffer is too small for the database
+    BufferFull,
+    /// An I/O error
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooSmall => f.write_str("invalid file format, buffer too small"),
+            Error::BadMagic => f.write_str("invalid file format, magic mismatch"),
+            Error::ByteOrder => f.write_str("invalid file format, byte order mismatch"),
+            Error::BadChunkSize { size } => {
+                write!(f, "invalid file format, chunk size {} out of bounds", size)
+            }
+            Error::TableOutOfBounds { table } => {
+                write!(f, "invalid table data: table {} out of bounds", table)
+            }
+            Error::BackwardLinkOutOfBounds => {
+                f.write_str("invalid backward link data: out of bounds")
+            }
+            Error::CorruptRecord { offset } => write!(f, "corrupt record at offset {}", offset),
+            #[cfg(feature = "alloc")]
+            Error::UnknownNormalizer { name } => write!(f, "unknown key normalizer {:?}", name),
+            Error::MissingNormalizer => {
+                f.write_str("invalid file format, normalizer section missing")
+            }
+            #[cfg(feature = "alloc")]
+            Error::DuplicateKey { key } => write!(f, "duplicate key {:?}", key.as_bstr()),
+            Error::DuplicateId { id } => write!(f, "duplicate id {}", id),
+            Error::KeyTooLong { len } => write!(f, "key of {} bytes is too long", len),
+            Error::TooLarge { size } => {
+                write!(
+                    f,
+                    "database of {} bytes exceeds the format size limit",
+                    size
+                )
+            }
+            Error::IdTooLarge { id } => write!(f, "id {} does not fit the backward link array", id),
+            Error::TooManyRecords => f.write_str("too many records for the database"),
+            Error::BufferFull => f.write_str("output buffer is full"),
+            #[cfg(feature = "std")]
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Approximate key lookup by Levenshtein distance
+//!
+//! Candidates are found with a padded bigram index: a single byte edit changes
+//! at most two bigrams, so a key within distance `d` of a query shares at least
+//! `max(len_a, len_b) + 1 - 2d` bigrams with it. Candidates are then checked
+//! with the exact distance.
+use std::collections::{BTreeMap, HashMap};
+
+use bstr::{BStr, ByteSlice};
+
+use crate::{ByteOrder, read_record};
+
+/// Sentinel byte padding both ends of a key
+const PAD: u8 = 0;
+
+/// In-memory bigram index over the records of a database
+#[derive(Debug, Default)]
+pub(crate) struct FuzzyIndex {
+    /// Record offsets
+    records: Vec<u64>,
+    /// Indices into `records` grouped by key length
+    by_len: BTreeMap<usize, Vec<u32>>,
+    /// Indices into `records` for each padded bigram, once per occurrence
+    postings: HashMap<u16, Vec<u32>>,
+}
+
+/// Padded bigrams of `key`, `key.len() + 1` of them
+fn bigrams(key: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    let first = std::iter::once(PAD).chain(key.iter().copied());
+    let second = key.iter().copied().chain(std::iter::once(PAD));
+    first.zip(second).map(|(a, b)| u16::from_be_bytes([a, b]))
+}
+
+/// Levenshtein distance between `a` and `b` in bytes, `None` if it exceeds `max`
+fn levenshtein(a: &[u8], b: &[u8], max: u32) -> Option<u32> {
+    let max = max as usize;
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for (i, &ca) in a.iter().enumerate() {
+        curr[0] = i + 1;
+        let mut row_min = curr[0];
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+            row_min = row_min.min(curr[j + 1]);
+        }
+        // Distances never decrease along a row, stop as soon as all exceed `max`
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    let distance = prev[b.len()];
+    (distance <= max).then_some(distance as u32)
+}
+
+impl FuzzyIndex {
+    /// Build the index from `(offset, key)` pairs of every record
+    pub(crate) fn build<'a>(records: impl Iterator<Item = (u64, &'a [u8])>) -> Self {
+        let mut index = Self::default();
+        for (offset, key) in records {
+            let i = index.records.len() as u32;
+            index.records.push(offset);
+            index.by_len.entry(key.len()).or_default().push(i);
+            for gram in bigrams(key) {
+                index.postings.entry(gram).or_default().push(i);
+            }
+        }
+        index
+    }
+
+    /// Find the keys within `max_distance` of `key`, closest first
+    pub(crate) fn search<'a>(
+        &self,
+        buffer: &'a [u8],
+        order: ByteOrder,
+        key: &[u8],
+        max_distance: u32,
+        limit: usize,
+    ) -> Vec<(&'a BStr, u32, u32)> {
+        let d = max_distance as usize;
+        let record = |i: u32| read_record(buffer, self.records[i as usize] as usize, order).ok();
+        let mut matches = Vec::new();
+        if key.len() < 2 * d {
+            // No bigram needs to be shared, check every key of a compatible length
+            let min_len = key.len().saturating_sub(d);
+            let max_len = key.len().saturating_add(d).saturating_add(1);
+            for &i in self
+                .by_len
+                .range(min_len..max_len)
+                .flat_map(|(_, group)| group)
+            {
+                let Some((id, found)) = record(i) else {
+                    continue;
+                };
+                if let Some(distance) = levenshtein(key, found, max_distance) {
+                    matches.push((found.as_bstr(), id, distance));
+                }
+            }
+        } else {
+            let mut counts: HashMap<u32, usize> = HashMap::new();
+            for gram in bigrams(key) {
+                for &i in self.postings.get(&gram).into_iter().flatten() {
+                    *counts.entry(i).or_default() += 1;
+                }
+            }
+            for (i, count) in counts {
+                let Some((id, found)) = record(i) else {
+                    continue;
+                };
+                // Repeated bigrams may be over-counted, which only admits more candidates
+                if count + 2 * d < key.len().max(found.len()) + 1 {
+                    continue;
+                }
+                if let Some(distance) = levenshtein(key, found, max_distance) {
+                    matches.push((found.as_bstr(), id, distance));
+                }
+            }
+        }
+        matches.sort_unstable_by(|a, b| (a.2, a.0, a.1).cmp(&(b.2, b.0, b.1)));
+        matches.truncate(limit);
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::levenshtein;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein(b"kitten", b"sitting", 3), Some(3));
+        assert_eq!(levenshtein(b"kitten", b"sitting", 2), None);
+        assert_eq!(levenshtein(b"", b"abc", 3), Some(3));
+        assert_eq!(levenshtein(b"abc", b"abc", 0), Some(0));
+        assert_eq!(levenshtein(b"abc", b"acb", 1), None);
+        assert_eq!(levenshtein(b"abc", b"abcdef", 2), None);
+    }
+}
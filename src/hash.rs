@@ -29,6 +29,10 @@
 // ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
 // POSSIBILITY OF SUCH DAMAGE.
 
+//! Hash function used to select the hash table and bucket of a key
+
+use crate::NUM_TABLES;
+
 const JHASH_INITVAL: u32 = 0xdeadbeef;
 
 #[inline(always)]
@@ -86,18 +90,19 @@ fn jhash_final(mut a: u32, mut b: u32, mut c: u32) -> u32 {
 /// Jenkins hash function for CQDB.
 ///
 /// `length` is passed separately from `key.len()` because CQDB hashes include
-/// a virtual NUL terminator (`length = key.len() + 1`).
+/// a virtual NUL terminator (`length = key.len() + 1`). Words are read
+/// little-endian like lookup3's `hashlittle`, so hashes match on every host.
 #[inline]
 #[must_use]
-pub fn jhash(mut key: &[u8], mut length: u32, initval: u32) -> u32 {
+pub(crate) fn jhash(mut key: &[u8], mut length: u32, initval: u32) -> u32 {
     let mut a = JHASH_INITVAL.wrapping_add(length).wrapping_add(initval);
     let mut b = a;
     let mut c = a;
 
     while length > 12 {
-        a = a.wrapping_add(u32::from_ne_bytes([key[0], key[1], key[2], key[3]]));
-        b = b.wrapping_add(u32::from_ne_bytes([key[4], key[5], key[6], key[7]]));
-        c = c.wrapping_add(u32::from_ne_bytes([key[8], key[9], key[10], key[11]]));
+        a = a.wrapping_add(u32::from_le_bytes([key[0], key[1], key[2], key[3]]));
+        b = b.wrapping_add(u32::from_le_bytes([key[4], key[5], key[6], key[7]]));
+        c = c.wrapping_add(u32::from_le_bytes([key[8], key[9], key[10], key[11]]));
         jhash_mix(&mut a, &mut b, &mut c);
         key = &key[12..];
         length -= 12;
@@ -131,9 +136,68 @@ pub fn jhash(mut key: &[u8], mut length: u32, initval: u32) -> u32 {
     jhash_final(a, b, c)
 }
 
+/// Hash value of a CQDB key
+///
+/// Computing it once lets the same key be looked up in several databases
+/// without rehashing, see [`CQDB::to_id_hashed`](crate::CQDB::to_id_hashed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyHash(u32);
+
+impl KeyHash {
+    /// Hash a key the way CQDB does, including its virtual NUL terminator
+    #[inline]
+    #[must_use]
+    pub fn new<K: AsRef<[u8]>>(key: K) -> Self {
+        let key = key.as_ref();
+        Self(jhash(key, key.len() as u32 + 1, 0))
+    }
+
+    /// Wrap a previously computed hash value
+    #[inline]
+    pub const fn from_raw(hash: u32) -> Self {
+        Self(hash)
+    }
+
+    /// The raw hash value
+    #[inline]
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    /// Index of the hash table the key is stored in
+    #[inline]
+    pub const fn table(self) -> usize {
+        (self.0 % NUM_TABLES as u32) as usize
+    }
+
+    /// Index of the first bucket probed in a hash table of `num` buckets
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num` is 0, empty tables have no bucket to probe.
+    #[inline]
+    pub const fn bucket(self, num: u32) -> u32 {
+        (self.0 >> 8) % num
+    }
+}
+
+impl From<u32> for KeyHash {
+    #[inline]
+    fn from(hash: u32) -> Self {
+        Self(hash)
+    }
+}
+
+impl From<KeyHash> for u32 {
+    #[inline]
+    fn from(hash: KeyHash) -> Self {
+        hash.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::jhash;
+    use super::{KeyHash, jhash};
 
     #[test]
     fn test_jhash_multiple_of_12() {
@@ -187,6 +251,16 @@ mod tests {
         assert_eq!(h1, h2);
     }
 
+    #[test]
+    fn test_key_hash() {
+        let hash = KeyHash::new("0123456789ab");
+        assert_eq!(hash.value(), 2677502765);
+        assert_eq!(hash, KeyHash::from_raw(2677502765));
+        assert_eq!(hash.table(), 2677502765 % 256);
+        assert_eq!(hash.bucket(10), (2677502765 >> 8) % 10);
+        assert_eq!(u32::from(hash), 2677502765);
+    }
+
     #[test]
     fn test_jhash_initval_matters() {
         let key = b"test_key";
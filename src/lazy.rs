@@ -0,0 +1,266 @@
+//! On-demand reader that fetches only the bytes each lookup needs
+use std::{
+    fmt,
+    io::{Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+use bstr::BString;
+
+use crate::{
+    Error, Flag, Format, KeyHash, KeyNormalizer, Layout,
+    normalize::{self, NORMALIZER_TAG},
+    section,
+};
+
+/// Size of a cached block
+const BLOCK_SIZE: usize = 4096;
+
+/// Constant quark database reader over a seekable stream
+///
+/// Only the chunk header is read when opening. Each lookup then reads the
+/// buckets and records it needs, optionally through a small block cache, and
+/// returns owned values. Validation matches [`CQDB::new`](crate::CQDB::new).
+pub struct LazyCQDB<R> {
+    reader: R,
+    /// Stream position of the chunk start
+    begin: u64,
+    /// Parsed chunk layout
+    layout: Layout,
+    /// Cached blocks by block index, least recently used first
+    cache: Vec<(usize, Box<[u8]>)>,
+    /// Maximum number of cached blocks, 0 disables the cache
+    cache_blocks: usize,
+}
+
+impl<R> fmt::Debug for LazyCQDB<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyCQDB")
+            .field("begin", &self.begin)
+            .field("header", &self.layout.header)
+            .field("num", &self.layout.num)
+            .field("cache_blocks", &self.cache_blocks)
+            .finish()
+    }
+}
+
+impl<R: Read + Seek> LazyCQDB<R> {
+    /// Open the database chunk starting at the current stream position
+    pub fn new(reader: R) -> Result<Self, Error> {
+        Self::open(reader, None)
+    }
+
+    /// Open the database chunk starting at the current stream position, providing a custom normalizer
+    ///
+    /// See [`CQDB::from_storage_with_normalizer`](crate::CQDB::from_storage_with_normalizer).
+    pub fn new_with_normalizer<N: KeyNormalizer + 'static>(
+        reader: R,
+        normalizer: N,
+    ) -> Result<Self, Error> {
+        let normalizer: Arc<dyn KeyNormalizer> = Arc::new(normalizer);
+        Self::open(reader, Some(&normalizer))
+    }
+
+    fn open(mut reader: R, normalizer: Option<&Arc<dyn KeyNormalizer>>) -> Result<Self, Error> {
+        let begin = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        let len = usize::try_from(end.saturating_sub(begin)).unwrap_or(usize::MAX);
+        if len < Format::Standard.header_size() {
+            return Err(Error::TooSmall);
+        }
+        // Enough for either header, parse_header checks the chunk id's one fits
+        let mut head = vec![0u8; len.min(Format::Wide.header_size())];
+        reader.seek(SeekFrom::Start(begin))?;
+        reader.read_exact(&mut head)?;
+        let layout = Layout::parse_header(&head, len)?;
+        let mut db = Self {
+            reader,
+            begin,
+            layout,
+            cache: Vec::new(),
+            cache_blocks: 0,
+        };
+        if db.layout.header.flag & Flag::NORMALIZED.bits() != 0 {
+            db.layout.normalizer = Some(db.find_normalizer(normalizer)?);
+        }
+        Ok(db)
+    }
+
+    /// Read the normalizer section among the extension sections, like [`normalize::find`]
+    fn find_normalizer(
+        &mut self,
+        custom: Option<&Arc<dyn KeyNormalizer>>,
+    ) -> Result<Arc<dyn KeyNormalizer>, Error> {
+        let size = self.layout.header.size as usize;
+        let format = self.layout.format;
+        let mut offset = self.layout.sections_offset;
+        while offset + format.section_header_size() <= size {
+            let mut head = [0u8; 12];
+            let head = &mut head[..format.section_header_size()];
+            self.read_at(offset, head)?;
+            let len = section::payload_size(head, self.layout.order, format);
+            let payload = offset + format.section_header_size();
+            let Some(end) = payload.checked_add(len).filter(|&end| end <= size) else {
+                break;
+            };
+            if &head[..4] == NORMALIZER_TAG {
+                let mut name = vec![0; len];
+                self.read_at(payload, &mut name)?;
+                return normalize::resolve(&name, custom);
+            }
+            offset = end;
+        }
+        Err(Error::MissingNormalizer)
+    }
+
+    /// Cache up to `blocks` recently read 4 KiB blocks
+    pub fn with_cache(mut self, blocks: usize) -> Self {
+        self.cache_blocks = blocks;
+        self.cache.truncate(blocks);
+        self
+    }
+
+    /// Get the number of associations in the database
+    #[inline]
+    pub fn num(&self) -> u32 {
+        self.layout.num
+    }
+
+    /// Consume the reader, returning the underlying stream
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Retrieve the identifier associated with a key
+    ///
+    /// The key is normalized first if the database was written with a [`KeyNormalizer`].
+    /// Read errors and corrupt records are reported as `None`, see [`LazyCQDB::try_to_id`].
+    pub fn to_id<K: AsRef<[u8]>>(&mut self, key: K) -> Option<u32> {
+        self.try_to_id(key).ok()?
+    }
+
+    /// Retrieve the identifier associated with a key, reporting read errors and corrupt records
+    pub fn try_to_id<K: AsRef<[u8]>>(&mut self, key: K) -> Result<Option<u32>, Error> {
+        let key = self.layout.normalize(key.as_ref());
+        let key = &key[..];
+        let hash = KeyHash::new(key);
+        let table = self.layout.tables[hash.table()];
+        let n = table.num;
+        if n == 0 {
+            return Ok(None);
+        }
+        let size = self.layout.format.bucket_size();
+        let mut k = hash.bucket(n);
+        // Bounded by the table size so a table without a vacant bucket terminates
+        for _ in 0..n {
+            let mut bucket = [0u8; 12];
+            let bucket = &mut bucket[..size];
+            self.read_at(table.offset + (k as usize) * size, bucket)?;
+            let (bucket_hash, offset) = self.layout.bucket(bucket, 0, 0);
+            if offset == 0 {
+                break;
+            }
+            if bucket_hash == hash.value() {
+                let (id, found) = self.read_record(offset as usize)?;
+                if found == key {
+                    return Ok(Some(id));
+                }
+            }
+            k = (k + 1) % n;
+        }
+        Ok(None)
+    }
+
+    /// Retrieve the string associated with an identifier
+    ///
+    /// Read errors and corrupt records are reported as `None`, see [`LazyCQDB::try_to_str`].
+    pub fn to_str(&mut self, id: u32) -> Option<BString> {
+        self.try_to_str(id).ok()?
+    }
+
+    /// Retrieve the string associated with an identifier, reporting read errors and corrupt records
+    pub fn try_to_str(&mut self, id: u32) -> Result<Option<BString>, Error> {
+        // Check if the current database supports the backward lookup
+        if self.layout.bwd_offset == 0 || id >= self.layout.header.bwd_size {
+            return Ok(None);
+        }
+        let format = self.layout.format;
+        let mut link = [0u8; 8];
+        let link = &mut link[..format.offset_size()];
+        self.read_at(
+            self.layout.bwd_offset + (id as usize) * format.offset_size(),
+            link,
+        )?;
+        let offset = format.read_offset(self.layout.order, link, 0);
+        if offset == 0 {
+            return Ok(None);
+        }
+        let (_, key) = self.read_record(offset as usize)?;
+        Ok(Some(BString::from(key)))
+    }
+
+    /// Read the `(id, key)` pair of the record at `offset`, without the trailing NUL
+    fn read_record(&mut self, offset: usize) -> Result<(u32, Vec<u8>), Error> {
+        let size = self.layout.header.size as usize;
+        let corrupt = Error::CorruptRecord { offset };
+        if offset.checked_add(8).is_none_or(|end| end > size) {
+            return Err(corrupt);
+        }
+        let mut rec = [0u8; 8];
+        self.read_at(offset, &mut rec)?;
+        let id = self.layout.order.u32_from([rec[0], rec[1], rec[2], rec[3]]);
+        let ksize = self.layout.order.u32_from([rec[4], rec[5], rec[6], rec[7]]) as usize;
+        // ksize includes NUL
+        let Some(len) = ksize.checked_sub(1) else {
+            return Err(corrupt);
+        };
+        if (offset + 8).checked_add(len).is_none_or(|end| end > size) {
+            return Err(corrupt);
+        }
+        let mut key = vec![0; len];
+        self.read_at(offset + 8, &mut key)?;
+        Ok((id, key))
+    }
+
+    /// Fill `buf` from chunk offset `offset`, the range must be within the chunk
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        if self.cache_blocks == 0 {
+            self.reader
+                .seek(SeekFrom::Start(self.begin + offset as u64))?;
+            self.reader.read_exact(buf)?;
+            return Ok(());
+        }
+        let mut pos = offset;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let block = self.block(pos / BLOCK_SIZE)?;
+            let start = pos % BLOCK_SIZE;
+            let n = (block.len() - start).min(buf.len() - filled);
+            buf[filled..filled + n].copy_from_slice(&block[start..start + n]);
+            filled += n;
+            pos += n;
+        }
+        Ok(())
+    }
+
+    /// Get a block through the cache, reading it from the stream if needed
+    fn block(&mut self, index: usize) -> Result<&[u8], Error> {
+        if let Some(i) = self.cache.iter().position(|(cached, _)| *cached == index) {
+            // Move to the most recently used end
+            let entry = self.cache.remove(i);
+            self.cache.push(entry);
+        } else {
+            let start = index * BLOCK_SIZE;
+            let len = BLOCK_SIZE.min(self.layout.header.size as usize - start);
+            let mut data = vec![0; len].into_boxed_slice();
+            self.reader
+                .seek(SeekFrom::Start(self.begin + start as u64))?;
+            self.reader.read_exact(&mut data)?;
+            if self.cache.len() >= self.cache_blocks {
+                self.cache.remove(0);
+            }
+            self.cache.push((index, data));
+        }
+        Ok(&self.cache[self.cache.len() - 1].1)
+    }
+}
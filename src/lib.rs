@@ -1,17 +1,82 @@
 //! Rust implementation of [Constant Quark Database](http://www.chokkan.org/software/cqdb/):
 //! a database library specialized for serialization and retrieval of static associations between strings and integer identifiers
-use std::{
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, sync::Arc, vec::Vec};
+use core::{
+    cell::Cell,
     fmt,
-    io::{self, Seek, SeekFrom, Write},
-    mem,
+    iter::FusedIterator,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
 };
+#[cfg(feature = "std")]
+use std::{io::Read, sync::OnceLock};
 
 use bitflags::bitflags;
 use bstr::{BStr, ByteSlice};
 
+mod batch;
+#[cfg(feature = "alloc")]
+mod builder;
+#[cfg(feature = "alloc")]
+mod dedup;
+mod error;
+#[cfg(feature = "std")]
+mod fuzzy;
 mod hash;
+#[cfg(feature = "std")]
+mod lazy;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "alloc")]
+mod normalize;
+mod section;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "alloc")]
+mod sink;
+mod sorted;
+#[cfg(feature = "alloc")]
+mod stats;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "alloc")]
+mod verify;
+
+#[cfg(feature = "mmap")]
+pub use memmap2::Mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::Advice;
+
+#[cfg(feature = "alloc")]
+pub use builder::CQDBBuilder;
+#[cfg(feature = "alloc")]
+pub use dedup::DuplicatePolicy;
+pub use error::Error;
+pub use hash::KeyHash;
+#[cfg(feature = "std")]
+pub use lazy::LazyCQDB;
+#[cfg(feature = "alloc")]
+pub use normalize::{AsciiLowercase, KeyNormalizer};
+#[cfg(feature = "unicode")]
+pub use normalize::{CaseFold, Nfc, Nfkc};
+#[cfg(feature = "serde")]
+pub use serialize::{KeyFormat, Serialized};
+#[cfg(feature = "alloc")]
+pub use sink::{BufSink, Sink};
+pub use sorted::SortedIter;
+#[cfg(feature = "alloc")]
+pub use stats::Stats;
+#[cfg(feature = "std")]
+pub use stream::CQDBStreamWriter;
+#[cfg(feature = "alloc")]
+pub use verify::{Issue, VerifyReport};
 
-const CHUNK_ID: &[u8; 4] = b"CQDB";
 const BYTEORDER_CHECK: u32 = 0x62445371;
 const NUM_TABLES: usize = 256;
 
@@ -23,21 +88,172 @@ bitflags! {
         const NONE = 0;
         /// A reverse lookup array is omitted
         const ONEWAY = 0x00000001;
+        /// A sorted key index is appended for prefix and range queries
+        const SORTED_INDEX = 0x00000002;
+        /// Keys are normalized, the [`KeyNormalizer`] is recorded in an extension section
+        ///
+        /// Writers set this flag with their normalizer and ignore it in the flags passed to them.
+        const NORMALIZED = 0x00000004;
     }
 }
 
-/// Read a little-endian u32 directly from a buffer at the given offset.
-/// Uses a single slice bounds check instead of 4 individual byte accesses.
-/// Panics on out-of-bounds (callers must validate buffer structure upfront).
-#[inline(always)]
-fn read_u32_le(buf: &[u8], offset: usize) -> u32 {
-    let b = &buf[offset..offset + 4];
-    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+/// Byte order of the integers in a database
+///
+/// The bundled C library always writes little-endian integers. Big-endian
+/// databases come from other writers, the reader detects the order from the
+/// byte-order indicator in the header. Key hashes do not depend on it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ByteOrder {
+    /// Little-endian, the default
+    #[default]
+    LittleEndian,
+    /// Big-endian
+    BigEndian,
+}
+
+impl ByteOrder {
+    /// Byte order of the host
+    pub const NATIVE: Self = if cfg!(target_endian = "little") {
+        Self::LittleEndian
+    } else {
+        Self::BigEndian
+    };
+
+    #[inline(always)]
+    fn u32_from(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Self::LittleEndian => u32::from_le_bytes(bytes),
+            Self::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    /// Read a u32 directly from a buffer at the given offset.
+    /// Uses a single slice bounds check instead of 4 individual byte accesses.
+    /// Panics on out-of-bounds (callers must validate buffer structure upfront).
+    #[inline(always)]
+    fn read_u32(self, buf: &[u8], offset: usize) -> u32 {
+        let b = &buf[offset..offset + 4];
+        self.u32_from([b[0], b[1], b[2], b[3]])
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    fn pack_u32(self, value: u32) -> [u8; 4] {
+        match self {
+            Self::LittleEndian => value.to_le_bytes(),
+            Self::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    /// Read a u64 directly from a buffer at the given offset, see [`ByteOrder::read_u32`]
+    #[inline(always)]
+    fn read_u64(self, buf: &[u8], offset: usize) -> u64 {
+        let b: [u8; 8] = buf[offset..offset + 8].try_into().unwrap();
+        match self {
+            Self::LittleEndian => u64::from_le_bytes(b),
+            Self::BigEndian => u64::from_be_bytes(b),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    fn pack_u64(self, value: u64) -> [u8; 8] {
+        match self {
+            Self::LittleEndian => value.to_le_bytes(),
+            Self::BigEndian => value.to_be_bytes(),
+        }
+    }
 }
 
-#[inline(always)]
-fn pack_u32(value: u32) -> [u8; 4] {
-    value.to_le_bytes()
+/// On-disk format of a database
+///
+/// Both formats share the layout of the chunk header, hash tables, records and
+/// backward link array, but differ in the width of the offsets stored in them.
+/// Identifiers and key sizes are 32-bit in both.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// `CQDB` chunks with 32-bit offsets, readable by the C library, the default
+    ///
+    /// A database in this format is at most 4 GiB.
+    #[default]
+    Standard,
+    /// `CQ64` chunks with 64-bit offsets, for databases larger than 4 GiB
+    Wide,
+}
+
+impl Format {
+    /// Chunk identifier
+    #[inline]
+    const fn chunk_id(self) -> &'static [u8; 4] {
+        match self {
+            Self::Standard => b"CQDB",
+            Self::Wide => b"CQ64",
+        }
+    }
+
+    /// Size of an offset, in the header, table references, buckets, backward links,
+    /// sorted index entries and section headers
+    #[inline(always)]
+    const fn offset_size(self) -> usize {
+        match self {
+            Self::Standard => 4,
+            Self::Wide => 8,
+        }
+    }
+
+    /// Size of the fixed chunk header: chunk id, size, flag, byte-order indicator,
+    /// backward array size and offset
+    #[inline(always)]
+    const fn fixed_header_size(self) -> usize {
+        16 + 2 * self.offset_size()
+    }
+
+    /// Size of the chunk header and table references, where the records start
+    #[inline(always)]
+    const fn header_size(self) -> usize {
+        self.fixed_header_size() + (self.offset_size() + 4) * NUM_TABLES
+    }
+
+    /// Size of a bucket: hash value and record offset
+    #[inline(always)]
+    const fn bucket_size(self) -> usize {
+        4 + self.offset_size()
+    }
+
+    /// Size of an extension section header: tag and payload size
+    #[inline(always)]
+    const fn section_header_size(self) -> usize {
+        4 + self.offset_size()
+    }
+
+    /// Largest offset the format can store
+    #[cfg(feature = "alloc")]
+    #[inline]
+    const fn max_offset(self) -> u64 {
+        match self {
+            Self::Standard => u32::MAX as u64,
+            Self::Wide => u64::MAX,
+        }
+    }
+
+    /// Read an offset at `pos`, panics on out-of-bounds like [`ByteOrder::read_u32`]
+    #[inline(always)]
+    fn read_offset(self, order: ByteOrder, buf: &[u8], pos: usize) -> u64 {
+        match self {
+            Self::Standard => u64::from(order.read_u32(buf, pos)),
+            Self::Wide => order.read_u64(buf, pos),
+        }
+    }
+
+    /// Append an offset, which must be at most [`Format::max_offset`]
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    fn push_offset(self, order: ByteOrder, value: u64, buf: &mut Vec<u8>) {
+        match self {
+            Self::Standard => buf.extend_from_slice(&order.pack_u32(value as u32)),
+            Self::Wide => buf.extend_from_slice(&order.pack_u64(value)),
+        }
+    }
 }
 
 /// Zero-copy hash table reference into the buffer
@@ -50,10 +266,35 @@ struct ReadTable {
 }
 
 /// Constant quark database (CQDB)
+///
+/// The reader is generic over its storage: by default it borrows a `&'a [u8]`,
+/// but any `AsRef<[u8]>` owner such as `Vec<u8>`, `Box<[u8]>` or `Arc<[u8]>`
+/// can be used to get a self-contained reader, see [`OwnedCQDB`].
+///
+/// The reader is safe to use on untrusted input: for any buffer, its methods
+/// return an error or `None` instead of panicking or probing forever.
+/// This is checked by the cargo-fuzz targets in `fuzz/`.
 #[derive(Clone)]
-pub struct CQDB<'a> {
+pub struct CQDB<'a, S = &'a [u8]> {
     /// Database file buffer
-    buffer: &'a [u8],
+    buffer: S,
+    /// Parsed chunk layout
+    layout: Layout,
+    /// Bigram index for approximate lookups, built on first use
+    #[cfg(feature = "std")]
+    fuzzy: OnceLock<Arc<fuzzy::FuzzyIndex>>,
+    _marker: PhantomData<&'a [u8]>,
+}
+
+/// A CQDB reader that owns its buffer.
+///
+/// With the default `Arc<[u8]>` storage it is `Send + Sync + 'static` and cheap to clone.
+#[cfg(feature = "alloc")]
+pub type OwnedCQDB<S = Arc<[u8]>> = CQDB<'static, S>;
+
+/// Parsed chunk header and table references, independent of the buffer storage
+#[derive(Clone)]
+struct Layout {
     /// Chunk header
     header: Header,
     /// Hash tables (string -> id), zero-copy references into buffer
@@ -62,16 +303,34 @@ pub struct CQDB<'a> {
     bwd_offset: usize,
     /// Number of key/data pairs
     num: u32,
+    /// Offset succeeding the hash tables and backward link array, where extension sections start
+    sections_offset: usize,
+    /// Sorted key index, if present
+    sorted: Option<sorted::SortedIndex>,
+    /// Byte order of the integers in the buffer
+    order: ByteOrder,
+    /// Width of the offsets in the buffer
+    format: Format,
+    /// Normalizer applied to keys, if any
+    #[cfg(feature = "alloc")]
+    normalizer: Option<Arc<dyn KeyNormalizer>>,
 }
 
+/// Normalizer passed to the reader, none can be without the `alloc` feature
+#[cfg(feature = "alloc")]
+type SharedNormalizer = Arc<dyn KeyNormalizer>;
+#[cfg(not(feature = "alloc"))]
+type SharedNormalizer = core::convert::Infallible;
+
 /// CQDB chunk header
 #[derive(Debug, Clone)]
-#[repr(C)]
+// Some fields are only read by the integrity check and statistics
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
 struct Header {
-    /// Chunk identifier, "CQDB"
+    /// Chunk identifier, "CQDB" or "CQ64"
     chunk_id: [u8; 4],
     /// Chunk size including this header
-    size: u32,
+    size: u64,
     /// Global flags
     flag: u32,
     /// Byte-order indicator
@@ -79,70 +338,155 @@ struct Header {
     /// Number of elements in the backward array
     bwd_size: u32,
     /// Offset to the backward array
-    bwd_offset: u32,
+    bwd_offset: u64,
 }
 
 /// A hash table (used by writer)
+#[cfg(feature = "alloc")]
 #[derive(Debug, Clone, Default)]
 struct Table {
-    size: usize,
     /// Number of elements in the table
     num: u32,
-    /// Array of Bucket
-    bucket: Vec<Bucket>,
+    /// Hash values of the records
+    hashes: Vec<u32>,
+    /// Offset addresses to the records, parallel to `hashes`
+    offsets: Offsets,
 }
 
-#[repr(C)]
-struct TableRef {
-    /// Offset to a hash table
-    offset: u32,
-    /// Number of elements in the hash table
-    num: u32,
+/// Record offsets kept by the writer, no wider than the format needs
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+enum Offsets {
+    Standard(Vec<u32>),
+    Wide(Vec<u64>),
+}
+
+#[cfg(feature = "alloc")]
+impl Default for Offsets {
+    fn default() -> Self {
+        Self::new(Format::Standard)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Offsets {
+    fn new(format: Format) -> Self {
+        match format {
+            Format::Standard => Self::Standard(Vec::new()),
+            Format::Wide => Self::Wide(Vec::new()),
+        }
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> u64 {
+        match self {
+            Self::Standard(offsets) => u64::from(offsets[i]),
+            Self::Wide(offsets) => offsets[i],
+        }
+    }
+
+    /// Set an offset, which must be at most the format's [`Format::max_offset`]
+    #[inline]
+    fn set(&mut self, i: usize, offset: u64) {
+        match self {
+            Self::Standard(offsets) => offsets[i] = offset as u32,
+            Self::Wide(offsets) => offsets[i] = offset,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, offset: u64) {
+        match self {
+            Self::Standard(offsets) => offsets.push(offset as u32),
+            Self::Wide(offsets) => offsets.push(offset),
+        }
+    }
+
+    /// Grow or shrink to `len` offsets, filling with zeros
+    fn resize(&mut self, len: usize) {
+        match self {
+            Self::Standard(offsets) => offsets.resize(len, 0),
+            Self::Wide(offsets) => offsets.resize(len, 0),
+        }
+    }
+
+    /// Convert to 64-bit offsets, moving every nonzero offset by `by`
+    #[cfg(feature = "std")]
+    fn widen(&mut self, by: u64) {
+        let wide = match self {
+            Self::Standard(offsets) => offsets
+                .iter()
+                .map(|&offset| if offset != 0 { offset as u64 + by } else { 0 })
+                .collect(),
+            Self::Wide(_) => unreachable!("offsets are already wide"),
+        };
+        *self = Self::Wide(wide);
+    }
 }
 
-/// An element of a hash table
+/// An element of a hash table as written
+#[cfg(feature = "alloc")]
 #[derive(Debug, Default, Clone, Copy)]
-#[repr(C)]
 struct Bucket {
     /// Hash value of the record
     hash: u32,
     /// Offset address to the actual record
-    offset: u32,
+    offset: u64,
 }
 
 /// Writer for a constant quark database
-pub struct CQDBWriter<T: Write + Seek> {
-    writer: T,
+///
+/// The output is any [`Sink`]: a `Write + Seek` stream with the `std` feature,
+/// or an in-memory [`BufSink`].
+#[cfg(feature = "alloc")]
+pub struct CQDBWriter<T: Sink> {
+    /// Output stream, `None` once finished
+    writer: Option<T>,
     /// Operation flag
     flag: Flag,
     /// Offset address to the head of this database
-    begin: u32,
+    begin: u64,
     /// Offset address to a new key/data pair
-    current: u32,
+    current: u64,
+    /// Number of records written
+    num: u32,
     /// Hash tables (string -> id)
     tables: [Table; NUM_TABLES],
     /// Backlink array
-    bwd: Vec<u32>,
+    bwd: Offsets,
     bwd_num: u32,
     /// Number of elements in the backlink array
     bwd_size: u32,
+    /// Keys for the sorted index, if enabled
+    sorted: Option<sorted::SortedKeys>,
+    /// Byte order of the integers written
+    order: ByteOrder,
+    /// Width of the offsets written
+    format: Format,
+    /// Normalizer applied to keys, if any
+    normalizer: Option<Arc<dyn KeyNormalizer>>,
+    /// Keys and ids put so far, unless duplicates are allowed
+    dedup: Option<dedup::Dedup>,
 }
 
-impl<'a> fmt::Debug for CQDB<'a> {
+impl<'a, S> fmt::Debug for CQDB<'a, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CQDB")
-            .field("header", &self.header)
-            .field("bwd_offset", &self.bwd_offset)
-            .field("num", &self.num)
+            .field("header", &self.layout.header)
+            .field("bwd_offset", &self.layout.bwd_offset)
+            .field("num", &self.layout.num)
             .finish()
     }
 }
 
-impl<T: Write + Seek + fmt::Debug> fmt::Debug for CQDBWriter<T> {
+#[cfg(feature = "alloc")]
+impl<T: Sink + fmt::Debug> fmt::Debug for CQDBWriter<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CQDBWriter")
             .field("writer", &self.writer)
             .field("flag", &self.flag)
+            .field("order", &self.order)
+            .field("format", &self.format)
             .field("begin", &self.begin)
             .field("current", &self.current)
             .field("bwd", &self.bwd)
@@ -153,118 +497,533 @@ impl<T: Write + Seek + fmt::Debug> fmt::Debug for CQDBWriter<T> {
 }
 
 impl<'a> CQDB<'a> {
-    pub fn new(buf: &'a [u8]) -> io::Result<Self> {
-        let min_size = mem::size_of::<Header>() + mem::size_of::<TableRef>() * NUM_TABLES;
-        if buf.len() < min_size {
-            // The minimum size of a valid CQDB
-            return Err(io::Error::other("invalid file format"));
+    /// Open a database on a borrowed buffer
+    ///
+    /// The database is the chunk at the start of `buf`; any data after
+    /// the chunk size recorded in its header is ignored.
+    pub fn new(buf: &'a [u8]) -> Result<Self, Error> {
+        Self::from_storage(buf)
+    }
+
+    /// Open the database chunk starting at `offset` in `buf`
+    ///
+    /// Offsets inside the chunk are relative to its start, as written by a
+    /// [`CQDBWriter`] on a stream positioned at `offset`.
+    pub fn at(buf: &'a [u8], offset: usize) -> Result<Self, Error> {
+        Self::new(buf.get(offset..).ok_or(Error::TooSmall)?)
+    }
+
+    /// An iterator over the consecutive database chunks in `buf`
+    pub fn chunks(buf: &'a [u8]) -> Chunks<'a> {
+        Chunks { buf, offset: 0 }
+    }
+}
+
+#[cfg(feature = "std")]
+impl OwnedCQDB {
+    /// Read a whole database from a reader into a shared, owned buffer
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Self::from_storage(Arc::from(buf))
+    }
+}
+
+impl<'a, S: AsRef<[u8]>> CQDB<'a, S> {
+    /// Open a database on any buffer storage, e.g. `Vec<u8>`, `Box<[u8]>` or `Arc<[u8]>`
+    pub fn from_storage(storage: S) -> Result<Self, Error> {
+        Self::open_with(storage, None)
+    }
+
+    /// Open a database on any buffer storage, providing a custom normalizer
+    ///
+    /// `normalizer` is used if the database was written with a normalizer of the
+    /// same name, built-in normalizers are resolved by [`CQDB::from_storage`] already.
+    #[cfg(feature = "alloc")]
+    pub fn from_storage_with_normalizer<N: KeyNormalizer + 'static>(
+        storage: S,
+        normalizer: N,
+    ) -> Result<Self, Error> {
+        let normalizer: Arc<dyn KeyNormalizer> = Arc::new(normalizer);
+        Self::open_with(storage, Some(&normalizer))
+    }
+
+    fn open_with(storage: S, normalizer: Option<&SharedNormalizer>) -> Result<Self, Error> {
+        let layout = Layout::parse(storage.as_ref(), normalizer)?;
+        Ok(Self {
+            buffer: storage,
+            layout,
+            #[cfg(feature = "std")]
+            fuzzy: OnceLock::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// The database chunk, bounds validated in new()
+    #[inline]
+    fn chunk(&self) -> &[u8] {
+        &self.buffer.as_ref()[..self.layout.header.size as usize]
+    }
+
+    /// Get the number of associations in the database
+    #[inline]
+    pub fn num(&self) -> u32 {
+        self.layout.num
+    }
+
+    /// Get the global flags the database was written with
+    #[inline]
+    pub fn flag(&self) -> Flag {
+        Flag::from_bits_retain(self.layout.header.flag)
+    }
+
+    /// Get the byte order the database was written in
+    #[inline]
+    pub fn byte_order(&self) -> ByteOrder {
+        self.layout.order
+    }
+
+    /// Returns `true` if the database was written without a backward link array, see [`Flag::ONEWAY`]
+    #[inline]
+    pub fn is_oneway(&self) -> bool {
+        self.flag().contains(Flag::ONEWAY)
+    }
+
+    /// Get the format the database was written in
+    #[inline]
+    pub fn format(&self) -> Format {
+        self.layout.format
+    }
+
+    /// Get the chunk size including the header
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.layout.header.size
+    }
+
+    /// Get the number of elements in the backward link array
+    #[inline]
+    pub fn bwd_size(&self) -> u32 {
+        self.layout.header.bwd_size
+    }
+
+    /// Collect statistics about the shape of the database
+    ///
+    /// This walks every bucket of every hash table.
+    #[cfg(feature = "alloc")]
+    pub fn stats(&self) -> Stats {
+        self.layout.stats(self.chunk())
+    }
+
+    /// Get the normalizer applied to keys, if the database was written with one
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn normalizer(&self) -> Option<&dyn KeyNormalizer> {
+        self.layout.normalizer.as_deref()
+    }
+
+    /// Normalize a key the way the database stores it
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        self.layout.normalize(key)
+    }
+
+    /// Retrieve the identifier associated with a string
+    ///
+    /// Keys are arbitrary bytes, so anything [`CQDBWriter::put`] accepts can be looked up.
+    /// The key is normalized first if the database was written with a [`KeyNormalizer`].
+    #[inline]
+    pub fn to_id<K: AsRef<[u8]>>(&self, key: K) -> Option<u32> {
+        self.try_to_id(key).ok()?
+    }
+
+    /// Retrieve the identifier associated with a string
+    ///
+    /// Unlike [`CQDB::to_id`], a corrupt record is reported as an error instead of `None`.
+    #[inline]
+    // Keys are borrowed as is without the `alloc` feature
+    #[cfg_attr(not(feature = "alloc"), allow(clippy::needless_borrow))]
+    pub fn try_to_id<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<u32>, Error> {
+        let key = self.layout.normalize(key.as_ref());
+        self.layout.try_to_id(self.chunk(), &key)
+    }
+
+    /// Retrieve the identifier associated with a key whose hash is already known
+    ///
+    /// `hash` must be [`KeyHash::new`] of `key`, otherwise the key is not found.
+    /// The key is not normalized, see [`CQDB::normalize`].
+    #[inline]
+    pub fn to_id_hashed<K: AsRef<[u8]>>(&self, key: K, hash: KeyHash) -> Option<u32> {
+        self.layout
+            .try_to_id_hashed(self.chunk(), key.as_ref(), hash)
+            .ok()?
+    }
+
+    /// An iterator over the records stored with `hash`, in the order a lookup probes them
+    pub fn candidates(&self, hash: KeyHash) -> Candidates<'_> {
+        let table = &self.layout.tables[hash.table()];
+        Candidates {
+            buffer: self.chunk(),
+            base: table.offset,
+            num: table.num,
+            next: if table.num > 0 {
+                hash.bucket(table.num)
+            } else {
+                0
+            },
+            remaining: table.num,
+            hash: hash.value(),
+            order: self.layout.order,
+            format: self.layout.format,
+        }
+    }
+
+    /// Retrieve the identifiers associated with a batch of keys
+    ///
+    /// `ids[i]` receives the result of `to_id(&keys[i])`. Keys are hashed and their
+    /// buckets and records prefetched ahead of comparison, so many lookups are in
+    /// flight at once and memory latency is hidden on large databases.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` and `ids` have different lengths.
+    pub fn to_ids<K: AsRef<[u8]>>(&self, keys: &[K], ids: &mut [Option<u32>]) {
+        assert_eq!(
+            keys.len(),
+            ids.len(),
+            "keys and ids must have the same length"
+        );
+        self.layout.to_ids(self.chunk(), keys, ids)
+    }
+
+    /// Retrieve the string associated with an identifier
+    #[inline]
+    pub fn to_str(&self, id: u32) -> Option<&BStr> {
+        self.layout.try_to_str(self.chunk(), id).ok()?
+    }
+
+    /// Retrieve the string associated with an identifier
+    ///
+    /// Unlike [`CQDB::to_str`], a corrupt record is reported as an error instead of `None`.
+    #[inline]
+    pub fn try_to_str(&self, id: u32) -> Result<Option<&BStr>, Error> {
+        self.layout.try_to_str(self.chunk(), id)
+    }
+
+    /// Find the keys closest to `key` within a Levenshtein distance of `max_distance`
+    ///
+    /// Returns up to `limit` `(key, id, distance)` tuples ordered by distance, then key.
+    /// Distances count byte edits. The first call builds an in-memory bigram index over
+    /// all records, which is shared by clones made after it.
+    #[cfg(feature = "std")]
+    pub fn fuzzy<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        max_distance: u32,
+        limit: usize,
+    ) -> Vec<(&BStr, u32, u32)> {
+        let index = self.fuzzy.get_or_init(|| {
+            let records = self.records().map_while(Result::ok);
+            Arc::new(fuzzy::FuzzyIndex::build(
+                records.map(|record| (record.offset, record.key.as_bytes())),
+            ))
+        });
+        index.search(
+            self.chunk(),
+            self.layout.order,
+            &self.layout.normalize(key.as_ref()),
+            max_distance,
+            limit,
+        )
+    }
+
+    /// Check the integrity of the whole database
+    ///
+    /// Unlike [`CQDB::new`], which only validates the header and table bounds,
+    /// this walks every bucket, record and backward link and reports every problem found.
+    #[cfg(feature = "alloc")]
+    pub fn verify(&self) -> VerifyReport {
+        self.layout.verify(self.chunk())
+    }
+
+    /// An iterator visiting all id, string pairs in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            buffer: self.chunk(),
+            layout: &self.layout,
+            next: 0,
+        }
+    }
+
+    /// An iterator visiting all id, string pairs in order, skipping unset ids.
+    ///
+    /// Unlike [`CQDB::iter`] this does not stop at the first gap in the ids.
+    pub fn iter_all(&self) -> RangeIter<'_> {
+        self.iter_range(..)
+    }
+
+    /// An iterator visiting the id, string pairs with ids in `range` in order, skipping unset ids.
+    ///
+    /// Creating the iterator is cheap. Its exact length is counted over the backward
+    /// links left in the range the first time it is asked for, by `len`, `size_hint`
+    /// or adapters such as `collect`, and kept up to date afterwards.
+    pub fn iter_range<R: RangeBounds<u32>>(&self, range: R) -> RangeIter<'_> {
+        let size = if self.layout.bwd_offset > 0 {
+            self.layout.header.bwd_size
+        } else {
+            0
+        };
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => size,
+        };
+        let end = end.min(size);
+        let start = start.min(end);
+        RangeIter {
+            buffer: self.chunk(),
+            layout: &self.layout,
+            front: start,
+            back: end,
+            remaining: Cell::new(None),
+        }
+    }
+
+    /// Returns `true` if the database has a sorted key index, see [`Flag::SORTED_INDEX`]
+    #[inline]
+    pub fn has_sorted_index(&self) -> bool {
+        self.layout.sorted.is_some()
+    }
+
+    /// An iterator visiting the key, id pairs whose key starts with `prefix`, in lexicographic order
+    ///
+    /// Returns `None` if the database was written without [`Flag::SORTED_INDEX`].
+    #[cfg_attr(not(feature = "alloc"), allow(clippy::needless_borrow))]
+    pub fn prefix<K: AsRef<[u8]>>(&self, prefix: K) -> Option<SortedIter<'_>> {
+        let index = self.layout.sorted.as_ref()?;
+        let prefix = self.layout.normalize(prefix.as_ref());
+        Some(index.prefix(self.chunk(), &prefix))
+    }
+
+    /// An iterator visiting the key, id pairs whose key is in `range`, in lexicographic order
+    ///
+    /// Returns `None` if the database was written without [`Flag::SORTED_INDEX`].
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Option<SortedIter<'_>> {
+        let index = self.layout.sorted.as_ref()?;
+        let start = range
+            .start_bound()
+            .map(|key| self.layout.normalize(key.as_ref()));
+        let end = range
+            .end_bound()
+            .map(|key| self.layout.normalize(key.as_ref()));
+        Some(index.range(
+            self.chunk(),
+            start.as_ref().map(|key| &key[..]),
+            end.as_ref().map(|key| &key[..]),
+        ))
+    }
+
+    /// An iterator visiting every record in storage order by scanning the record area.
+    ///
+    /// Unlike [`CQDB::iter`] this does not need the backward link array,
+    /// so it also lists databases written with [`Flag::ONEWAY`].
+    pub fn records(&self) -> Records<'_> {
+        let buffer = self.chunk();
+        Records {
+            buffer,
+            offset: self.layout.format.header_size(),
+            end: self.layout.records_end(buffer.len()),
+            order: self.layout.order,
+        }
+    }
+}
+
+impl Layout {
+    fn parse(buf: &[u8], normalizer: Option<&SharedNormalizer>) -> Result<Self, Error> {
+        let mut layout = Self::parse_header(buf, buf.len())?;
+        let chunk = &buf[..layout.header.size as usize];
+        layout.sorted = sorted::SortedIndex::find(&layout, chunk);
+        #[cfg(feature = "alloc")]
+        {
+            layout.normalizer = normalize::find(&layout, chunk, normalizer)?;
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            let _ = normalizer;
+            // Normalizers need allocation, keys could not be looked up correctly
+            if layout.header.flag & Flag::NORMALIZED.bits() != 0 {
+                return Err(Error::MissingNormalizer);
+            }
+        }
+        Ok(layout)
+    }
+
+    /// Parse the chunk header and table references at the start of `buf`
+    /// and validate them against `len`, the number of bytes available from the chunk start
+    fn parse_header(buf: &[u8], len: usize) -> Result<Self, Error> {
+        // The minimum size of a valid CQDB, the standard header is the smaller one
+        if buf.len() < Format::Standard.header_size() || len < Format::Standard.header_size() {
+            return Err(Error::TooSmall);
         }
         // Check the file chunkid
-        if &buf[0..4] != CHUNK_ID {
-            return Err(io::Error::other("invalid file format, magic mismatch"));
-        }
-        let chunk_size = read_u32_le(buf, 4);
-        let flag = read_u32_le(buf, 8);
-        let byte_order = read_u32_le(buf, 12);
-        // Check the consistency of byte order
-        if byte_order != BYTEORDER_CHECK {
-            return Err(io::Error::other("invalid file format, byte order mismatch"));
-        }
-        let bwd_size = read_u32_le(buf, 16);
-        let bwd_offset_raw = read_u32_le(buf, 20);
+        let format = match &buf[0..4] {
+            id if id == Format::Standard.chunk_id() => Format::Standard,
+            id if id == Format::Wide.chunk_id() => Format::Wide,
+            _ => return Err(Error::BadMagic),
+        };
+        let min_size = format.header_size();
+        if buf.len() < min_size || len < min_size {
+            return Err(Error::TooSmall);
+        }
+        let o = format.offset_size();
+        // Detect the byte order from the indicator, which reads swapped in the other order
+        let order = match ByteOrder::LittleEndian.read_u32(buf, 8 + o) {
+            BYTEORDER_CHECK => ByteOrder::LittleEndian,
+            check if check == BYTEORDER_CHECK.swap_bytes() => ByteOrder::BigEndian,
+            _ => return Err(Error::ByteOrder),
+        };
+        let chunk_size = format.read_offset(order, buf, 4);
+        let flag = order.read_u32(buf, 4 + o);
+        // The chunk may be followed by other data, bound everything by its size
+        let size = match usize::try_from(chunk_size) {
+            Ok(size) if size >= min_size && size <= len => size,
+            _ => return Err(Error::BadChunkSize { size: chunk_size }),
+        };
+        let bwd_size = order.read_u32(buf, 12 + o);
+        let bwd_offset_raw = format.read_offset(order, buf, 16 + o);
         let header = Header {
-            chunk_id: *CHUNK_ID,
+            chunk_id: *format.chunk_id(),
             size: chunk_size,
             flag,
-            byteorder: byte_order,
+            byteorder: BYTEORDER_CHECK,
             bwd_size,
             bwd_offset: bwd_offset_raw,
         };
 
         // Parse table references (zero-copy: just store offset + count)
+        let mut sections_offset = min_size;
         let mut num_db = 0u32;
         let mut tables = [ReadTable::default(); NUM_TABLES];
-        let mut index = 24; // After 6 × u32 header fields
-        for table in &mut tables {
-            let table_offset = read_u32_le(buf, index) as usize;
-            index += 4;
-            let table_num = read_u32_le(buf, index);
+        let mut index = format.fixed_header_size();
+        for (i, table) in tables.iter_mut().enumerate() {
+            let table_offset = format.read_offset(order, buf, index);
+            index += o;
+            let table_num = order.read_u32(buf, index);
             index += 4;
             if table_offset > 0 {
                 // Validate that bucket data fits within the buffer (checked arithmetic for overflow)
                 let end = (table_num as usize)
-                    .checked_mul(8)
-                    .and_then(|bytes| table_offset.checked_add(bytes));
+                    .checked_mul(format.bucket_size())
+                    .zip(usize::try_from(table_offset).ok())
+                    .and_then(|(bytes, offset)| offset.checked_add(bytes));
                 match end {
-                    Some(end) if end <= buf.len() => {
-                        table.offset = table_offset;
+                    Some(end) if end <= size => {
+                        table.offset = table_offset as usize;
                         table.num = table_num;
+                        sections_offset = sections_offset.max(end);
                     }
-                    _ => return Err(io::Error::other("invalid table data: out of bounds")),
+                    _ => return Err(Error::TableOutOfBounds { table: i }),
                 }
             }
-            // The number of records is the half of the table size
-            num_db += table_num / 2;
+            // The number of records is the half of the table size,
+            // saturating as tables of a malformed file may overlap
+            num_db = num_db.saturating_add(table_num / 2);
         }
 
         // Validate backward link array bounds
         let bwd_offset = if bwd_offset_raw > 0 {
-            let off = bwd_offset_raw as usize;
             let end = (bwd_size as usize)
-                .checked_mul(4)
-                .and_then(|bytes| off.checked_add(bytes));
+                .checked_mul(o)
+                .zip(usize::try_from(bwd_offset_raw).ok())
+                .and_then(|(bytes, offset)| offset.checked_add(bytes));
             match end {
-                Some(end) if end <= buf.len() => off,
-                _ => {
-                    return Err(io::Error::other(
-                        "invalid backward link data: out of bounds",
-                    ));
+                Some(end) if end <= size => {
+                    sections_offset = sections_offset.max(end);
+                    bwd_offset_raw as usize
                 }
+                _ => return Err(Error::BackwardLinkOutOfBounds),
             }
         } else {
             0
         };
 
         Ok(Self {
-            buffer: buf,
             header,
             tables,
             bwd_offset,
             num: num_db,
+            sections_offset,
+            sorted: None,
+            order,
+            format,
+            #[cfg(feature = "alloc")]
+            normalizer: None,
         })
     }
 
-    /// Get the number of associations in the database
+    /// Offset succeeding the last record: the writer places the hash tables,
+    /// then the backward link array, then the extension sections, right after the records.
+    fn records_end(&self, len: usize) -> usize {
+        self.tables
+            .iter()
+            .filter(|table| table.num > 0)
+            .map(|table| table.offset)
+            .min()
+            .or((self.bwd_offset > 0).then_some(self.bwd_offset))
+            .unwrap_or(self.sections_offset)
+            .min(len)
+    }
+
+    /// Normalize a key with the normalizer of the database, if any
+    #[cfg(feature = "alloc")]
     #[inline]
-    pub fn num(&self) -> u32 {
-        self.num
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        match &self.normalizer {
+            Some(normalizer) => normalizer.normalize(key),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Keys are never normalized without the `alloc` feature, see [`Layout::parse`]
+    #[cfg(not(feature = "alloc"))]
+    #[inline]
+    fn normalize<'k>(&self, key: &'k [u8]) -> &'k [u8] {
+        key
+    }
+
+    #[inline]
+    fn try_to_id(&self, buffer: &[u8], key: &[u8]) -> Result<Option<u32>, Error> {
+        self.try_to_id_hashed(buffer, key, KeyHash::new(key))
     }
 
-    /// Retrieve the identifier associated with a string
     #[inline]
-    pub fn to_id(&self, s: &str) -> Option<u32> {
-        let hash = crate::hash::jhash(s.as_bytes(), s.len() as u32 + 1, 0);
-        let table = &self.tables[(hash % NUM_TABLES as u32) as usize];
+    fn try_to_id_hashed(
+        &self,
+        buffer: &[u8],
+        key: &[u8],
+        hash: KeyHash,
+    ) -> Result<Option<u32>, Error> {
+        let table = &self.tables[hash.table()];
+        let hash = hash.value();
         if table.num > 0 {
             let n = table.num;
             let base = table.offset;
             let mut k = (hash >> 8) % n;
-            loop {
-                // Single bounds check for both hash + offset (8 bytes)
-                let bk = &self.buffer[base + (k as usize) * 8..][..8];
-                let bucket_offset = u32::from_le_bytes([bk[4], bk[5], bk[6], bk[7]]);
+            // Bounded by the table size so a table without a vacant bucket terminates
+            for _ in 0..n {
+                let (bucket_hash, bucket_offset) = self.bucket(buffer, base, k);
                 if bucket_offset > 0 {
-                    let bucket_hash = u32::from_le_bytes([bk[0], bk[1], bk[2], bk[3]]);
                     if bucket_hash == hash {
-                        // Record reads use offsets from file content — use checked access
-                        let rec_start = bucket_offset as usize;
-                        let rec = self.buffer.get(rec_start..rec_start + 8)?;
-                        let value = u32::from_le_bytes([rec[0], rec[1], rec[2], rec[3]]);
-                        let ksize = (u32::from_le_bytes([rec[4], rec[5], rec[6], rec[7]]) as usize)
-                            .checked_sub(1)?; // ksize includes NUL
-                        let key_end = rec_start.checked_add(8 + ksize)?;
-                        if s.as_bytes() == self.buffer.get(rec_start + 8..key_end)? {
-                            return Some(value);
+                        let (value, found) =
+                            read_record(buffer, bucket_offset as usize, self.order)?;
+                        if key == found {
+                            return Ok(Some(value));
                         }
                     }
                 } else {
@@ -273,57 +1032,89 @@ impl<'a> CQDB<'a> {
                 k = (k + 1) % n;
             }
         }
-        None
+        Ok(None)
+    }
+
+    /// Hash value and record offset of bucket `k` of the bucket array at `base`
+    #[inline(always)]
+    fn bucket(&self, buffer: &[u8], base: usize, k: u32) -> (u32, u64) {
+        let size = self.format.bucket_size();
+        // Single bounds check for both hash + offset
+        let bk = &buffer[base + (k as usize) * size..][..size];
+        (
+            self.order.read_u32(bk, 0),
+            self.format.read_offset(self.order, bk, 4),
+        )
+    }
+
+    /// Backward link of `id`, 0 if unset, `id` must be less than the backward array size
+    #[inline(always)]
+    fn link(&self, buffer: &[u8], id: u32) -> u64 {
+        // bwd array read is safe: bounds validated in new()
+        let pos = self.bwd_offset + (id as usize) * self.format.offset_size();
+        self.format.read_offset(self.order, buffer, pos)
     }
 
-    /// Retrieve the string associated with an identifier
     #[inline]
-    pub fn to_str(&'a self, id: u32) -> Option<&'a BStr> {
+    fn try_to_str<'b>(&self, buffer: &'b [u8], id: u32) -> Result<Option<&'b BStr>, Error> {
         // Check if the current database supports the backward lookup
         if self.bwd_offset > 0 && id < self.header.bwd_size {
-            // bwd array read is safe: bounds validated in new()
-            let offset = read_u32_le(self.buffer, self.bwd_offset + (id as usize) * 4);
+            let offset = self.link(buffer, id);
             if offset > 0 {
-                // Record reads use offsets from file content — use checked access
-                let index = offset as usize + 4; // Skip id field
-                let rec = self.buffer.get(index..index + 4)?;
-                let value_size = (u32::from_le_bytes([rec[0], rec[1], rec[2], rec[3]]) as usize)
-                    .checked_sub(1)?; // includes NUL
-                let start = index + 4;
-                let end = start.checked_add(value_size)?;
-                return Some(self.buffer.get(start..end)?.as_bstr());
+                let (_, key) = read_record(buffer, offset as usize, self.order)?;
+                return Ok(Some(key.as_bstr()));
             }
         }
-        None
+        Ok(None)
     }
+}
 
-    /// An iterator visiting all id, string pairs in order.
-    pub fn iter(&'a self) -> Iter<'a> {
-        Iter { db: self, next: 0 }
-    }
+/// Read the `(id, key)` pair of the record at `offset`, without the trailing NUL.
+/// Record reads use offsets from file content — use checked access.
+#[inline]
+fn read_record(buffer: &[u8], offset: usize, order: ByteOrder) -> Result<(u32, &[u8]), Error> {
+    let corrupt = || Error::CorruptRecord { offset };
+    let rec = buffer
+        .get(offset..offset.checked_add(8).ok_or_else(corrupt)?)
+        .ok_or_else(corrupt)?;
+    let value = order.u32_from([rec[0], rec[1], rec[2], rec[3]]);
+    let ksize = (order.u32_from([rec[4], rec[5], rec[6], rec[7]]) as usize)
+        .checked_sub(1) // ksize includes NUL
+        .ok_or_else(corrupt)?;
+    let start = offset + 8;
+    let end = start.checked_add(ksize).ok_or_else(corrupt)?;
+    let key = buffer.get(start..end).ok_or_else(corrupt)?;
+    Ok((value, key))
 }
 
 /// CQDB iterator
 pub struct Iter<'a> {
-    db: &'a CQDB<'a>,
+    buffer: &'a [u8],
+    layout: &'a Layout,
     next: u32,
 }
 
 impl<'a> Iterator for Iter<'a> {
-    type Item = io::Result<(u32, &'a BStr)>;
+    type Item = Result<(u32, &'a BStr), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let id = self.next;
-        if let Some(s) = self.db.to_str(id) {
-            self.next += 1;
-            return Some(Ok((id, s)));
+        match self.layout.try_to_str(self.buffer, id) {
+            Ok(Some(s)) => {
+                self.next += 1;
+                Some(Ok((id, s)))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                self.next += 1;
+                Some(Err(err))
+            }
         }
-        None
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let remaining = if self.db.bwd_offset > 0 {
-            self.db.header.bwd_size.saturating_sub(self.next) as usize
+        let remaining = if self.layout.bwd_offset > 0 {
+            self.layout.header.bwd_size.saturating_sub(self.next) as usize
         } else {
             0
         };
@@ -331,8 +1122,8 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
-impl<'a> IntoIterator for &'a CQDB<'a> {
-    type Item = io::Result<(u32, &'a BStr)>;
+impl<'a, 'b, S: AsRef<[u8]>> IntoIterator for &'a CQDB<'b, S> {
+    type Item = Result<(u32, &'a BStr), Error>;
     type IntoIter = Iter<'a>;
 
     #[inline]
@@ -341,86 +1132,515 @@ impl<'a> IntoIterator for &'a CQDB<'a> {
     }
 }
 
-impl<T: Write + Seek> CQDBWriter<T> {
+/// Iterator over the records probed for a hash, see [`CQDB::candidates`]
+pub struct Candidates<'a> {
+    buffer: &'a [u8],
+    /// Offset of the bucket array
+    base: usize,
+    /// Number of buckets in the table
+    num: u32,
+    /// Next bucket to probe
+    next: u32,
+    /// Number of buckets left to probe
+    remaining: u32,
+    hash: u32,
+    order: ByteOrder,
+    format: Format,
+}
+
+impl<'a> Iterator for Candidates<'a> {
+    type Item = Result<Record<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            // Bucket read is safe: table bounds validated in new()
+            let pos = self.base + (self.next as usize) * self.format.bucket_size();
+            let bucket_hash = self.order.read_u32(self.buffer, pos);
+            let offset = self.format.read_offset(self.order, self.buffer, pos + 4);
+            self.next = (self.next + 1) % self.num;
+            if offset == 0 {
+                // A vacant bucket ends the probe sequence
+                self.remaining = 0;
+            } else if bucket_hash == self.hash {
+                return Some(read_record(self.buffer, offset as usize, self.order).map(
+                    |(id, key)| Record {
+                        id,
+                        key: key.as_bstr(),
+                        offset,
+                        hash: bucket_hash,
+                    },
+                ));
+            }
+        }
+        None
+    }
+}
+
+impl FusedIterator for Candidates<'_> {}
+
+/// Iterator over consecutive database chunks, see [`CQDB::chunks`]
+pub struct Chunks<'a> {
+    buf: &'a [u8],
+    /// Offset of the next chunk
+    offset: usize,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<CQDB<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        match CQDB::at(self.buf, self.offset) {
+            Ok(db) => {
+                self.offset += db.layout.header.size as usize;
+                Some(Ok(db))
+            }
+            Err(err) => {
+                // The size of an invalid chunk is unknown, stop walking
+                self.offset = self.buf.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl FusedIterator for Chunks<'_> {}
+
+/// Gap-tolerant CQDB iterator over a range of ids, see [`CQDB::iter_range`]
+pub struct RangeIter<'a> {
+    buffer: &'a [u8],
+    layout: &'a Layout,
+    /// Next id from the front
+    front: u32,
+    /// One past the next id from the back
+    back: u32,
+    /// Number of set ids in `front..back`, counted on demand
+    remaining: Cell<Option<usize>>,
+}
+
+impl<'a> RangeIter<'a> {
+    /// Backward link of `id`, 0 if unset
+    #[inline]
+    fn link(&self, id: u32) -> u64 {
+        // ids are clamped to the bounds validated in new()
+        self.layout.link(self.buffer, id)
+    }
+
+    /// Account for a set id leaving `front..back`
+    #[inline]
+    fn consume(&mut self) {
+        if let Some(remaining) = self.remaining.get_mut() {
+            *remaining -= 1;
+        }
+    }
+
+    #[inline]
+    fn resolve(&mut self, id: u32) -> Result<(u32, &'a BStr), Error> {
+        self.consume();
+        let (_, key) = read_record(self.buffer, self.link(id) as usize, self.layout.order)?;
+        Ok((id, key.as_bstr()))
+    }
+}
+
+impl<'a> Iterator for RangeIter<'a> {
+    type Item = Result<(u32, &'a BStr), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.front < self.back {
+            let id = self.front;
+            self.front += 1;
+            if self.link(id) > 0 {
+                return Some(self.resolve(id));
+            }
+        }
+        None
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+        // Skip over backward links without reading the records they point to
+        while n > 0 && self.front < self.back {
+            if self.link(self.front) > 0 {
+                self.consume();
+                n -= 1;
+            }
+            self.front += 1;
+        }
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining.get().unwrap_or_else(|| {
+            let remaining = (self.front..self.back)
+                .filter(|&id| self.link(id) > 0)
+                .count();
+            self.remaining.set(Some(remaining));
+            remaining
+        });
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for RangeIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.front < self.back {
+            self.back -= 1;
+            let id = self.back;
+            if self.link(id) > 0 {
+                return Some(self.resolve(id));
+            }
+        }
+        None
+    }
+}
+
+impl ExactSizeIterator for RangeIter<'_> {}
+
+impl FusedIterator for RangeIter<'_> {}
+
+/// A key/data pair stored in the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// Identifier of the record
+    pub id: u32,
+    /// Key of the record, without the NUL terminator
+    pub key: &'a BStr,
+    /// Offset of the record in the buffer
+    pub offset: u64,
+    /// Hash value of the key
+    pub hash: u32,
+}
+
+/// CQDB record iterator, see [`CQDB::records`]
+pub struct Records<'a> {
+    buffer: &'a [u8],
+    /// Offset of the next record
+    offset: usize,
+    /// Offset succeeding the last record
+    end: usize,
+    order: ByteOrder,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None;
+        }
+        let offset = self.offset;
+        match read_record(&self.buffer[..self.end], offset, self.order) {
+            Ok((id, key)) => {
+                // 8 bytes of id and key size, then the key and its NUL terminator
+                self.offset += 8 + key.len() + 1;
+                Some(Ok(Record {
+                    id,
+                    key: key.as_bstr(),
+                    offset: offset as u64,
+                    hash: KeyHash::new(key).value(),
+                }))
+            }
+            Err(err) => {
+                // The size of a corrupt record is unknown, stop scanning
+                self.offset = self.end;
+                Some(Err(err))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // The smallest record is an empty key: 8 bytes of header and a NUL byte
+        (0, Some(self.end.saturating_sub(self.offset) / 9))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Sink> CQDBWriter<T> {
     /// Create a new CQDB writer
-    pub fn new(writer: T) -> io::Result<Self> {
+    pub fn new(writer: T) -> Result<Self, Error> {
         Self::with_flag(writer, Flag::NONE)
     }
 
     /// Create a new CQDB writer with flag
-    pub fn with_flag(mut writer: T, flag: Flag) -> io::Result<Self> {
-        let begin = writer.stream_position()? as u32;
-        let current = (mem::size_of::<Header>() + mem::size_of::<TableRef>() * NUM_TABLES) as u32;
+    pub fn with_flag(writer: T, flag: Flag) -> Result<Self, Error> {
+        Self::with_byte_order(writer, flag, ByteOrder::LittleEndian)
+    }
+
+    /// Create a new CQDB writer with flag, writing integers in `order`
+    pub fn with_byte_order(mut writer: T, flag: Flag, order: ByteOrder) -> Result<Self, Error> {
+        let begin = writer.position()?;
+        let current = Format::Standard.header_size() as u64;
         // Move the file pointer to the offset to the first key/data pair
-        writer.seek(SeekFrom::Start((begin + current) as u64))?;
+        writer.seek_to(begin + current)?;
         Ok(Self {
-            writer,
-            flag,
+            writer: Some(writer),
+            // Only set_normalizer records a normalizer for the flag to refer to
+            flag: flag - Flag::NORMALIZED,
             begin,
             current,
-            tables: std::array::from_fn(|_| Table::default()),
-            bwd: Vec::new(),
+            num: 0,
+            tables: core::array::from_fn(|_| Table::default()),
+            bwd: Offsets::default(),
             bwd_num: 0,
             bwd_size: 0,
+            sorted: flag
+                .contains(Flag::SORTED_INDEX)
+                .then(sorted::SortedKeys::default),
+            order,
+            format: Format::Standard,
+            normalizer: None,
+            dedup: None,
         })
     }
 
+    /// Write the database in `format`, [`Format::Standard`] by default
+    ///
+    /// Records follow the header, so the format cannot change once they are
+    /// written and a standard database past 4 GiB fails with [`Error::TooLarge`].
+    /// [`CQDBBuilder`] and `CQDBStreamWriter` pick the format by size instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBWriter::put`].
+    pub fn with_format(mut self, format: Format) -> Result<Self, Error> {
+        self.set_format(format)?;
+        Ok(self)
+    }
+
+    pub(crate) fn set_format(&mut self, format: Format) -> Result<(), Error> {
+        assert!(
+            self.is_empty(),
+            "the format must be set before putting keys"
+        );
+        self.format = format;
+        self.current = format.header_size() as u64;
+        for table in &mut self.tables {
+            table.offsets = Offsets::new(format);
+        }
+        self.bwd = Offsets::new(format);
+        // Move the file pointer to the offset to the first key/data pair
+        let writer = self.writer.as_mut().expect("writer is finished");
+        writer.seek_to(self.begin + self.current)
+    }
+
+    /// Returns `true` if no association was put yet
+    fn is_empty(&self) -> bool {
+        self.tables.iter().all(|table| table.num == 0)
+            && self.dedup.as_ref().is_none_or(dedup::Dedup::is_empty)
+    }
+
+    /// Handle keys and ids that were already put according to `policy`
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBWriter::put`].
+    pub fn with_duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.set_duplicates(policy);
+        self
+    }
+
+    pub(crate) fn set_duplicates(&mut self, policy: DuplicatePolicy) {
+        assert!(
+            self.is_empty(),
+            "the duplicate policy must be set before putting keys"
+        );
+        self.dedup = (policy != DuplicatePolicy::Allow).then(|| dedup::Dedup::new(policy));
+    }
+
+    /// Normalize keys with `normalizer` before storing them, and record it in the database
+    /// so that readers normalize their queries the same way
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBWriter::put`].
+    pub fn with_normalizer<N: KeyNormalizer + 'static>(mut self, normalizer: N) -> Self {
+        self.set_normalizer(Arc::new(normalizer));
+        self
+    }
+
+    pub(crate) fn set_normalizer(&mut self, normalizer: Arc<dyn KeyNormalizer>) {
+        assert!(
+            self.is_empty(),
+            "the normalizer must be set before putting keys"
+        );
+        self.flag |= Flag::NORMALIZED;
+        self.normalizer = Some(normalizer);
+    }
+
     /// Put a string/identifier association to the database
-    pub fn put<K: AsRef<[u8]>>(&mut self, key: K, id: u32) -> io::Result<()> {
-        let key = key.as_ref();
-        let key_size = key.len() as u32 + 1; // includes NUL byte
+    ///
+    /// A key or id that was already put is handled according to the
+    /// [`DuplicatePolicy`] set with [`CQDBWriter::with_duplicates`].
+    pub fn put<K: AsRef<[u8]>>(&mut self, key: K, id: u32) -> Result<(), Error> {
+        let normalized;
+        let key = match &self.normalizer {
+            Some(normalizer) => {
+                normalized = normalizer.normalize(key.as_ref());
+                &normalized[..]
+            }
+            None => key.as_ref(),
+        };
+        self.put_normalized(key, id)
+    }
+
+    /// Put an association whose key is already normalized
+    pub(crate) fn put_normalized(&mut self, key: &[u8], id: u32) -> Result<(), Error> {
+        // Reject oversized records before the duplicate policy records them
+        let (key_size, hash) = self.check_record(key, id)?;
+        if let Some(dedup) = &mut self.dedup {
+            match dedup.check(key, id)? {
+                dedup::Action::Write => {}
+                dedup::Action::Skip => return Ok(()),
+            }
+        }
+        self.write_record(key, key_size, hash, id)
+    }
+
+    /// Size of the finished database with records up to `end`, `num` records and
+    /// `bwd_num` backward links
+    fn total_size(&self, end: u64, num: u64, bwd_num: u64) -> u64 {
+        let offset_size = self.format.offset_size() as u64;
+        let section_header_size = self.format.section_header_size() as u64;
+        let mut size = end + num * 2 * self.format.bucket_size() as u64;
+        if !self.flag.contains(Flag::ONEWAY) {
+            size += bwd_num * offset_size;
+        }
+        if self.sorted.is_some() {
+            size += section_header_size + num * offset_size;
+        }
+        if let Some(normalizer) = &self.normalizer {
+            size += section_header_size + normalizer.name().len() as u64;
+        }
+        size
+    }
+
+    /// Check that a record for `key` and `id` fits in the format, returning its
+    /// key size and hash value
+    fn check_record(&self, key: &[u8], id: u32) -> Result<(u32, u32), Error> {
+        let key_size = key_size(key.len())?;
+        // The number of backward links must fit in the header
+        if id == u32::MAX && !self.flag.contains(Flag::ONEWAY) {
+            return Err(Error::IdTooLarge { id });
+        }
+        let size = self.total_size(
+            self.current + 8 + key_size as u64,
+            self.num as u64 + 1,
+            (self.bwd_num as u64).max(id as u64 + 1),
+        );
+        if size > self.format.max_offset() {
+            return Err(Error::TooLarge { size });
+        }
+        // The header stores each table's bucket count, twice its records
         let hash = crate::hash::jhash(key, key_size, 0);
+        if self.num == u32::MAX || self.tables[hash as usize % 256].num >= u32::MAX / 2 {
+            return Err(Error::TooManyRecords);
+        }
+        Ok((key_size, hash))
+    }
+
+    /// Write a record and index it in the hash tables and backward link array
+    ///
+    /// `key_size` and `hash` are the result of [`CQDBWriter::check_record`] for the record.
+    fn write_record(&mut self, key: &[u8], key_size: u32, hash: u32, id: u32) -> Result<(), Error> {
+        let writer = self.writer.as_mut().expect("writer is finished");
         let table = &mut self.tables[hash as usize % 256];
         // Batch record write: [id(4) | key_size(4) | key | NUL]
         let record_len = 8 + key.len() + 1;
         if record_len <= 264 {
             let mut buf = [0u8; 264]; // 8 header + max 255 key + NUL
-            buf[0..4].copy_from_slice(&pack_u32(id));
-            buf[4..8].copy_from_slice(&pack_u32(key_size));
+            buf[0..4].copy_from_slice(&self.order.pack_u32(id));
+            buf[4..8].copy_from_slice(&self.order.pack_u32(key_size));
             buf[8..8 + key.len()].copy_from_slice(key);
             // buf[8 + key.len()] is already 0 (NUL)
-            self.writer.write_all(&buf[..record_len])?;
+            writer.write_all(&buf[..record_len])?;
         } else {
             // Fallback for very large keys
-            self.writer.write_all(&pack_u32(id))?;
-            self.writer.write_all(&pack_u32(key_size))?;
-            self.writer.write_all(key)?;
-            self.writer.write_all(b"\0")?;
-        }
-        // Expand the bucket if necessary
-        if table.size <= table.num as usize {
-            table.size = (table.size + 1) * 2;
-            table.bucket.resize(table.size, Bucket::default());
+            writer.write_all(&self.order.pack_u32(id))?;
+            writer.write_all(&self.order.pack_u32(key_size))?;
+            writer.write_all(key)?;
+            writer.write_all(b"\0")?;
         }
         // Set the hash value and current offset position
-        table.bucket[table.num as usize].hash = hash;
-        table.bucket[table.num as usize].offset = self.current;
+        table.hashes.push(hash);
+        table.offsets.push(self.current);
         table.num += 1;
         // Store the backlink if specified
         if !self.flag.contains(Flag::ONEWAY) {
             // Expand the backlink arrray if necessary
             if self.bwd_size <= id {
-                let mut size = self.bwd_size;
-                while size <= id {
+                let mut size = self.bwd_size as u64;
+                while size <= id as u64 {
                     size = (size + 1) * 2;
                 }
-                self.bwd.resize(size as usize, 0);
+                // No larger than the format can hold, checked to exceed id
+                let limit = self.format.max_offset() / self.format.offset_size() as u64;
+                let size = size.min(limit).min(u32::MAX as u64) as u32;
+                self.bwd.resize(size as usize);
                 self.bwd_size = size;
             }
             if self.bwd_num <= id {
                 self.bwd_num = id + 1;
             }
-            self.bwd[id as usize] = self.current;
+            self.bwd.set(id as usize, self.current);
+        }
+        if let Some(sorted) = &mut self.sorted {
+            sorted.push(key, self.current);
         }
         // Increment the current position
-        self.current += 4 + 4 + key_size;
+        self.current += 4 + 4 + key_size as u64;
+        self.num += 1;
         Ok(())
     }
 
+    /// Write the hash tables, backward link array and header, and flush the stream
+    ///
+    /// Returns the underlying writer, positioned after the database. On failure
+    /// the stream is rewound to where the database began.
+    ///
+    /// Dropping the writer also finishes the database, but silently ignores errors.
+    pub fn finish(mut self) -> Result<T, Error> {
+        self.finish_mut()
+    }
+
+    /// [`CQDBWriter::finish`] for writers embedded in another type, leaving nothing for Drop to do
+    pub(crate) fn finish_mut(&mut self) -> Result<T, Error> {
+        let result = self.finalize();
+        // Taking the writer keeps Drop from finishing again
+        let writer = self.writer.take().expect("writer is finished");
+        result.map(|()| writer)
+    }
+
+    /// Close the writer, rewinding to the beginning of the database on failure
+    fn finalize(&mut self) -> Result<(), Error> {
+        let result = self.close();
+        if result.is_err()
+            && let Some(writer) = &mut self.writer
+        {
+            let _ = writer.seek_to(self.begin);
+        }
+        result
+    }
+
     /// Close the writer, flush the file stream
-    fn close(&mut self) -> io::Result<()> {
+    fn close(&mut self) -> Result<(), Error> {
+        // Write the associations buffered for the duplicate policy
+        if let Some(dedup) = &mut self.dedup {
+            for (key, id) in dedup.take_pending() {
+                // Buffered records were checked before any of them was written
+                let (key_size, hash) = self.check_record(&key, id)?;
+                self.write_record(&key, key_size, hash, id)?;
+            }
+        }
+        let size = self.total_size(self.current, self.num as u64, self.bwd_num as u64);
+        if size > self.format.max_offset() {
+            return Err(Error::TooLarge { size });
+        }
+        let writer = self.writer.as_mut().expect("writer is finished");
+        let (order, format) = (self.order, self.format);
         let mut header = Header {
-            chunk_id: *CHUNK_ID,
+            chunk_id: *format.chunk_id(),
             flag: self.flag.bits(),
             byteorder: BYTEORDER_CHECK,
             bwd_offset: 0,
@@ -431,12 +1651,11 @@ impl<T: Write + Seek> CQDBWriter<T> {
         // the offset succeeding the last key/data pair.
         // Reuse dst Vec across tables to avoid per-table heap allocation.
         let mut dst: Vec<Bucket> = Vec::new();
-        #[cfg(not(target_endian = "little"))]
         let mut write_buf: Vec<u8> = Vec::new();
         for i in 0..NUM_TABLES {
             let table = &self.tables[i];
             // Do not write empty hash tables
-            if table.bucket.is_empty() {
+            if table.num == 0 {
                 continue;
             }
             // Actual bucket will have the double size; half elements
@@ -447,97 +1666,114 @@ impl<T: Write + Seek> CQDBWriter<T> {
             dst.clear();
             dst.resize(n_usize, Bucket::default());
             // Put hash elements to the bucket with the open-address method
-            for j in 0..table.num as usize {
-                let src = &table.bucket[j];
-                let mut k = (src.hash >> 8) % n;
+            for (j, &hash) in table.hashes.iter().enumerate() {
+                let mut k = (hash >> 8) % n;
                 // Find a vacant element
                 while dst[k as usize].offset != 0 {
                     k = (k + 1) % n;
                 }
                 // Store the hash element
-                dst[k as usize].hash = src.hash;
-                dst[k as usize].offset = src.offset;
+                dst[k as usize].hash = hash;
+                dst[k as usize].offset = table.offsets.get(j);
             }
-            // Write the entire bucket array for this table in one call.
-            // On LE platforms, Bucket repr(C) {u32, u32} matches the on-disk format.
-            #[cfg(target_endian = "little")]
-            {
-                let bytes =
-                    unsafe { std::slice::from_raw_parts(dst.as_ptr() as *const u8, n_usize * 8) };
-                self.writer.write_all(bytes)?;
-            }
-            #[cfg(not(target_endian = "little"))]
-            {
-                write_buf.clear();
-                write_buf.reserve(n_usize * 8);
-                for bucket in &dst[..n_usize] {
-                    write_buf.extend_from_slice(&pack_u32(bucket.hash));
-                    write_buf.extend_from_slice(&pack_u32(bucket.offset));
-                }
-                self.writer.write_all(&write_buf)?;
+            // Write the entire bucket array for this table in one call
+            write_buf.clear();
+            write_buf.reserve(n_usize * format.bucket_size());
+            for bucket in &dst[..n_usize] {
+                write_buf.extend_from_slice(&order.pack_u32(bucket.hash));
+                format.push_offset(order, bucket.offset, &mut write_buf);
             }
+            writer.write_all(&write_buf)?;
         }
         // Write the backlink array if specified
         if !self.flag.contains(Flag::ONEWAY) && self.bwd_size > 0 {
             // Store the offset to the head of this array
-            let current_offset = self.writer.stream_position()? as u32;
+            let current_offset = writer.position()?;
             header.bwd_offset = current_offset - self.begin;
             // Write all backward links in one call.
-            #[cfg(target_endian = "little")]
-            {
-                let bytes = unsafe {
-                    std::slice::from_raw_parts(
-                        self.bwd.as_ptr() as *const u8,
-                        self.bwd_num as usize * 4,
-                    )
-                };
-                self.writer.write_all(bytes)?;
-            }
-            #[cfg(not(target_endian = "little"))]
-            {
-                write_buf.clear();
-                write_buf.reserve(self.bwd_num as usize * 4);
-                for i in 0..self.bwd_num as usize {
-                    write_buf.extend_from_slice(&pack_u32(self.bwd[i]));
-                }
-                self.writer.write_all(&write_buf)?;
+            write_buf.clear();
+            write_buf.reserve(self.bwd_num as usize * format.offset_size());
+            for i in 0..self.bwd_num as usize {
+                format.push_offset(order, self.bwd.get(i), &mut write_buf);
             }
+            writer.write_all(&write_buf)?;
+        }
+        // Write the sorted key index section if specified
+        if let Some(sorted) = &mut self.sorted {
+            sorted.write(writer, order, format)?;
+        }
+        // Record the key normalizer if specified
+        if let Some(normalizer) = &self.normalizer {
+            normalize::write(writer, normalizer.as_ref(), order, format)?;
         }
         // Store the current position
-        let offset = self.writer.stream_position()? as u32;
+        let offset = writer.position()?;
         header.size = offset - self.begin;
         // Rewind the current position to the beginning
-        self.writer.seek(SeekFrom::Start(self.begin as u64))?;
-        // Write header + table references in a single batch (2072 bytes on stack)
-        let mut hdr_buf = [0u8; 24 + NUM_TABLES * 8];
-        hdr_buf[0..4].copy_from_slice(&header.chunk_id);
-        hdr_buf[4..8].copy_from_slice(&pack_u32(header.size));
-        hdr_buf[8..12].copy_from_slice(&pack_u32(header.flag));
-        hdr_buf[12..16].copy_from_slice(&pack_u32(header.byteorder));
-        hdr_buf[16..20].copy_from_slice(&pack_u32(header.bwd_size));
-        hdr_buf[20..24].copy_from_slice(&pack_u32(header.bwd_offset));
+        writer.seek_to(self.begin)?;
+        // Write header + table references in a single batch
+        write_buf.clear();
+        write_buf.reserve(format.header_size());
+        write_buf.extend_from_slice(&header.chunk_id);
+        format.push_offset(order, header.size, &mut write_buf);
+        write_buf.extend_from_slice(&order.pack_u32(header.flag));
+        write_buf.extend_from_slice(&order.pack_u32(header.byteorder));
+        write_buf.extend_from_slice(&order.pack_u32(header.bwd_size));
+        format.push_offset(order, header.bwd_offset, &mut write_buf);
         // Write references to hash tables. At this moment, self.current points
         // to the offset succeeding the last key/data pair.
         for i in 0..NUM_TABLES {
             let table_num = self.tables[i].num;
             // Offset to the hash table (or zero for non-existent tables)
             let table_offset = if table_num > 0 { self.current } else { 0 };
-            let off = 24 + i * 8;
-            hdr_buf[off..off + 4].copy_from_slice(&pack_u32(table_offset));
+            format.push_offset(order, table_offset, &mut write_buf);
             // Bucket size is double the number of elements
-            hdr_buf[off + 4..off + 8].copy_from_slice(&pack_u32(table_num * 2));
+            write_buf.extend_from_slice(&order.pack_u32(table_num * 2));
             // Advance the offset counter
-            self.current += table_num * 2 * std::mem::size_of::<Bucket>() as u32;
+            self.current += table_num as u64 * 2 * format.bucket_size() as u64;
         }
-        self.writer.write_all(&hdr_buf)?;
+        writer.write_all(&write_buf)?;
         // Seek to the last position
-        self.writer.seek(SeekFrom::Start(offset as u64))?;
-        Ok(())
+        writer.seek_to(offset)?;
+        writer.flush()
     }
 }
 
-impl<T: Write + Seek> Drop for CQDBWriter<T> {
+#[cfg(feature = "alloc")]
+impl<T: Sink> Drop for CQDBWriter<T> {
+    /// Finish the database if [`CQDBWriter::finish`] was not called, ignoring errors
     fn drop(&mut self) {
-        if let Ok(()) = self.close() {}
+        if self.writer.is_some() {
+            let _ = self.finalize();
+        }
+    }
+}
+
+/// Size of the record key for a key of `len` bytes, including the NUL byte
+#[cfg(feature = "alloc")]
+fn key_size(len: usize) -> Result<u32, Error> {
+    u32::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_add(1))
+        .ok_or(Error::KeyTooLong { len })
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::{Error, key_size};
+
+    #[test]
+    fn test_key_size_limit() {
+        assert_eq!(key_size(0).unwrap(), 1);
+        assert_eq!(key_size(u32::MAX as usize - 1).unwrap(), u32::MAX);
+        assert!(matches!(
+            key_size(u32::MAX as usize),
+            Err(Error::KeyTooLong { len }) if len == u32::MAX as usize
+        ));
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(
+            key_size(usize::MAX),
+            Err(Error::KeyTooLong { len: usize::MAX })
+        ));
     }
 }
@@ -0,0 +1,69 @@
+//! Memory-mapped database files
+use std::{fs::File, io, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{CQDB, Error};
+
+/// Access pattern hint for a memory-mapped database, passed to `madvise(2)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Advice {
+    /// No special treatment, the kernel default
+    Normal,
+    /// Expect random page references, disables read-ahead
+    Random,
+    /// Expect access in the near future, prefetches the whole mapping
+    WillNeed,
+}
+
+impl CQDB<'static, Mmap> {
+    /// Open a database file by memory-mapping it read-only
+    ///
+    /// The mapping is validated exactly like [`CQDB::new`]. Pages are shared with
+    /// other processes mapping the same file through the page cache.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other process,
+    /// while the database or any key borrowed from it is alive. Otherwise lookups
+    /// are undefined behavior and the process may receive `SIGBUS`, see
+    /// [`Mmap::map`].
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // Safety: upheld by the caller
+        let mmap = unsafe { Mmap::map(&file)? };
+        Self::from_storage(mmap)
+    }
+
+    /// Open a database file by memory-mapping it and apply an access pattern hint
+    ///
+    /// # Safety
+    ///
+    /// Same as [`CQDB::open`].
+    pub unsafe fn open_with_advice<P: AsRef<Path>>(path: P, advice: Advice) -> Result<Self, Error> {
+        // Safety: upheld by the caller
+        let db = unsafe { Self::open(path)? };
+        db.advise(advice)?;
+        Ok(db)
+    }
+
+    /// Advise the kernel about the expected access pattern of the mapping
+    ///
+    /// This is a no-op on platforms without `madvise`.
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let advice = match advice {
+                Advice::Normal => memmap2::Advice::Normal,
+                Advice::Random => memmap2::Advice::Random,
+                Advice::WillNeed => memmap2::Advice::WillNeed,
+            };
+            self.buffer.advise(advice)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = advice;
+            Ok(())
+        }
+    }
+}
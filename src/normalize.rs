@@ -0,0 +1,179 @@
+//! Key normalization for case and Unicode form insensitive lookups
+//!
+//! A [`CQDBWriter`](crate::CQDBWriter) with a normalizer stores normalized keys
+//! and records the normalizer name in an extension section, flagged by
+//! [`Flag::NORMALIZED`]. Readers normalize queries the same way automatically.
+use alloc::{borrow::Cow, string::String, sync::Arc};
+
+use crate::{ByteOrder, Error, Flag, Format, Layout, Sink, section};
+
+/// Section tag of the normalizer name
+pub(crate) const NORMALIZER_TAG: &[u8; 4] = b"NORM";
+
+/// A normalization applied to keys before they are stored or looked up
+pub trait KeyNormalizer: Send + Sync {
+    /// Name recorded in the database to select the same normalizer when reading
+    fn name(&self) -> &str;
+
+    /// Normalize a key
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]>;
+}
+
+/// Lowercase ASCII letters, leaving other bytes unchanged
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AsciiLowercase;
+
+impl KeyNormalizer for AsciiLowercase {
+    fn name(&self) -> &str {
+        "ascii-lowercase"
+    }
+
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        if key.iter().any(u8::is_ascii_uppercase) {
+            Cow::Owned(key.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+}
+
+/// Unicode default case folding
+///
+/// Keys that are not valid UTF-8 are left unchanged.
+#[cfg(feature = "unicode")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CaseFold;
+
+#[cfg(feature = "unicode")]
+impl KeyNormalizer for CaseFold {
+    fn name(&self) -> &str {
+        "case-fold"
+    }
+
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        use caseless::Caseless;
+
+        normalize_str(key, |s| Some(s.chars().default_case_fold().collect()))
+    }
+}
+
+/// Unicode canonical composition, Normalization Form C
+///
+/// Keys that are not valid UTF-8 are left unchanged.
+#[cfg(feature = "unicode")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Nfc;
+
+#[cfg(feature = "unicode")]
+impl KeyNormalizer for Nfc {
+    fn name(&self) -> &str {
+        "nfc"
+    }
+
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+
+        normalize_str(key, |s| match is_nfc_quick(s.chars()) {
+            IsNormalized::Yes => None,
+            _ => Some(s.nfc().collect()),
+        })
+    }
+}
+
+/// Unicode compatibility composition, Normalization Form KC
+///
+/// Keys that are not valid UTF-8 are left unchanged.
+#[cfg(feature = "unicode")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Nfkc;
+
+#[cfg(feature = "unicode")]
+impl KeyNormalizer for Nfkc {
+    fn name(&self) -> &str {
+        "nfkc"
+    }
+
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfkc_quick};
+
+        normalize_str(key, |s| match is_nfkc_quick(s.chars()) {
+            IsNormalized::Yes => None,
+            _ => Some(s.nfkc().collect()),
+        })
+    }
+}
+
+/// Apply `f` to a UTF-8 key, borrowing when `f` returns `None` or an equal string
+#[cfg(feature = "unicode")]
+fn normalize_str<'k>(key: &'k [u8], f: impl FnOnce(&str) -> Option<String>) -> Cow<'k, [u8]> {
+    let Ok(s) = core::str::from_utf8(key) else {
+        return Cow::Borrowed(key);
+    };
+    match f(s) {
+        Some(normalized) if normalized != s => Cow::Owned(normalized.into_bytes()),
+        _ => Cow::Borrowed(key),
+    }
+}
+
+/// The built-in normalizer named `name`
+fn builtin(name: &str) -> Option<Arc<dyn KeyNormalizer>> {
+    match name {
+        "ascii-lowercase" => Some(Arc::new(AsciiLowercase)),
+        #[cfg(feature = "unicode")]
+        "case-fold" => Some(Arc::new(CaseFold)),
+        #[cfg(feature = "unicode")]
+        "nfc" => Some(Arc::new(Nfc)),
+        #[cfg(feature = "unicode")]
+        "nfkc" => Some(Arc::new(Nfkc)),
+        _ => None,
+    }
+}
+
+/// Resolve the normalizer named by `name`, preferring `custom` if its name matches
+pub(crate) fn resolve(
+    name: &[u8],
+    custom: Option<&Arc<dyn KeyNormalizer>>,
+) -> Result<Arc<dyn KeyNormalizer>, Error> {
+    let unknown = || Error::UnknownNormalizer {
+        name: String::from_utf8_lossy(name).into_owned(),
+    };
+    let name = core::str::from_utf8(name).map_err(|_| unknown())?;
+    match custom {
+        Some(custom) if custom.name() == name => Ok(Arc::clone(custom)),
+        _ => builtin(name).ok_or_else(unknown),
+    }
+}
+
+/// Locate the normalizer of a parsed layout, `None` if keys are not normalized
+pub(crate) fn find(
+    layout: &Layout,
+    buffer: &[u8],
+    custom: Option<&Arc<dyn KeyNormalizer>>,
+) -> Result<Option<Arc<dyn KeyNormalizer>>, Error> {
+    if layout.header.flag & Flag::NORMALIZED.bits() == 0 {
+        return Ok(None);
+    }
+    let Some(payload) = section::find(
+        buffer,
+        layout.sections_offset,
+        layout.header.size as usize,
+        NORMALIZER_TAG,
+        layout.order,
+        layout.format,
+    ) else {
+        return Err(Error::MissingNormalizer);
+    };
+    resolve(&buffer[payload], custom).map(Some)
+}
+
+/// Write the normalizer section
+pub(crate) fn write<W: Sink>(
+    writer: &mut W,
+    normalizer: &dyn KeyNormalizer,
+    order: ByteOrder,
+    format: Format,
+) -> Result<(), Error> {
+    let name = normalizer.name().as_bytes();
+    section::write_header(writer, NORMALIZER_TAG, name.len() as u64, order, format)?;
+    writer.write_all(name)
+}
@@ -0,0 +1,88 @@
+//! Extension sections
+//!
+//! Optional sections are appended after the backward link array (or after the
+//! hash tables of [`Flag::ONEWAY`](crate::Flag::ONEWAY) databases) and are
+//! covered by the chunk size. Each section is laid out as
+//!
+//! ```text
+//! tag: [u8; 4] | size: u32 | payload: [u8; size]
+//! ```
+//!
+//! The size is written in the byte order of the database, and is a u64 in
+//! [`Format::Wide`](crate::Format::Wide) databases.
+//! Readers that do not know a section never look past the backward link array,
+//! so databases with extension sections stay readable by the original C library.
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{ByteOrder, Format};
+#[cfg(feature = "alloc")]
+use crate::{Error, Sink};
+
+/// Payload size stored in a section header, saturating where it does not fit usize
+#[inline]
+pub(crate) fn payload_size(header: &[u8], order: ByteOrder, format: Format) -> usize {
+    usize::try_from(format.read_offset(order, header, 4)).unwrap_or(usize::MAX)
+}
+
+/// Find the payload of the section tagged `tag` among the sections in `buffer[start..end]`
+pub(crate) fn find(
+    buffer: &[u8],
+    start: usize,
+    end: usize,
+    tag: &[u8; 4],
+    order: ByteOrder,
+    format: Format,
+) -> Option<Range<usize>> {
+    let header_size = format.section_header_size();
+    let end = end.min(buffer.len());
+    let mut offset = start;
+    while offset.checked_add(header_size)? <= end {
+        let payload = offset + header_size;
+        let size = payload_size(&buffer[offset..payload], order, format);
+        let payload_end = payload.checked_add(size).filter(|&e| e <= end)?;
+        if &buffer[offset..offset + 4] == tag {
+            return Some(payload..payload_end);
+        }
+        offset = payload_end;
+    }
+    None
+}
+
+/// Offset where the well-formed sections in `buffer[start..end]` stop, `end` if they tile it exactly
+#[cfg(feature = "alloc")]
+pub(crate) fn end(
+    buffer: &[u8],
+    start: usize,
+    end: usize,
+    order: ByteOrder,
+    format: Format,
+) -> usize {
+    let header_size = format.section_header_size();
+    let end = end.min(buffer.len());
+    let mut offset = start;
+    while offset + header_size <= end {
+        let size = payload_size(&buffer[offset..offset + header_size], order, format);
+        match (offset + header_size).checked_add(size) {
+            Some(next) if next <= end => offset = next,
+            _ => break,
+        }
+    }
+    offset
+}
+
+/// Write a section header, the caller writes `size` bytes of payload next
+#[cfg(feature = "alloc")]
+pub(crate) fn write_header<W: Sink>(
+    writer: &mut W,
+    tag: &[u8; 4],
+    size: u64,
+    order: ByteOrder,
+    format: Format,
+) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(format.section_header_size());
+    buf.extend_from_slice(tag);
+    format.push_offset(order, size, &mut buf);
+    writer.write_all(&buf)
+}
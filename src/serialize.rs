@@ -0,0 +1,290 @@
+//! serde integration
+//!
+//! A [`CQDB`] serializes as a map of key to id in record order, or as a sequence
+//! of `(id, key)` pairs with [`CQDB::as_pairs`]. [`CQDBWriter::put_deserialized`]
+//! reads either shape back. How keys are represented is chosen with [`KeyFormat`].
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use bstr::ByteSlice;
+use serde::{
+    de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeMap, SerializeSeq, Serializer},
+};
+
+use crate::{CQDB, CQDBWriter, Sink};
+
+/// Representation of keys when serializing and deserializing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum KeyFormat {
+    /// A string if the key is valid UTF-8, bytes otherwise
+    ///
+    /// Formats such as JSON reject byte map keys, use [`KeyFormat::Escaped`]
+    /// there when keys may not be UTF-8.
+    #[default]
+    Auto,
+    /// Always bytes
+    Bytes,
+    /// Always a string: invalid UTF-8 bytes are written as `\xNN` and a
+    /// backslash as `\\`, which deserializing reverses
+    Escaped,
+}
+
+/// Shape of a serialized database
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    Map,
+    Pairs,
+}
+
+/// A serializable view of a [`CQDB`], see [`CQDB::as_map`] and [`CQDB::as_pairs`]
+#[derive(Debug)]
+pub struct Serialized<'d, 'a, S> {
+    db: &'d CQDB<'a, S>,
+    shape: Shape,
+    keys: KeyFormat,
+}
+
+impl<'a, S: AsRef<[u8]>> CQDB<'a, S> {
+    /// Serialize as a map of key to id, with keys in the given format
+    pub fn as_map(&self, keys: KeyFormat) -> Serialized<'_, 'a, S> {
+        Serialized {
+            db: self,
+            shape: Shape::Map,
+            keys,
+        }
+    }
+
+    /// Serialize as a sequence of `(id, key)` pairs, with keys in the given format
+    pub fn as_pairs(&self, keys: KeyFormat) -> Serialized<'_, 'a, S> {
+        Serialized {
+            db: self,
+            shape: Shape::Pairs,
+            keys,
+        }
+    }
+}
+
+/// A key serialized in a [`KeyFormat`]
+struct FormattedKey<'k> {
+    key: &'k [u8],
+    format: KeyFormat,
+}
+
+impl ser::Serialize for FormattedKey<'_> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        match (self.format, core::str::from_utf8(self.key)) {
+            (KeyFormat::Bytes, _) | (KeyFormat::Auto, Err(_)) => {
+                serializer.serialize_bytes(self.key)
+            }
+            (KeyFormat::Auto, Ok(key)) => serializer.serialize_str(key),
+            (KeyFormat::Escaped, Ok(key)) if !key.contains('\\') => serializer.serialize_str(key),
+            (KeyFormat::Escaped, _) => serializer.serialize_str(&escape(self.key)),
+        }
+    }
+}
+
+impl<S: AsRef<[u8]>> ser::Serialize for Serialized<'_, '_, S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let records = self.db.records();
+        let len = Some(self.db.num() as usize);
+        match self.shape {
+            Shape::Map => {
+                let mut map = serializer.serialize_map(len)?;
+                for record in records {
+                    let record = record.map_err(ser::Error::custom)?;
+                    let key = FormattedKey {
+                        key: record.key,
+                        format: self.keys,
+                    };
+                    map.serialize_entry(&key, &record.id)?;
+                }
+                map.end()
+            }
+            Shape::Pairs => {
+                let mut seq = serializer.serialize_seq(len)?;
+                for record in records {
+                    let record = record.map_err(ser::Error::custom)?;
+                    let key = FormattedKey {
+                        key: record.key,
+                        format: self.keys,
+                    };
+                    seq.serialize_element(&(record.id, key))?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+/// Serializes as a map of key to id, see [`CQDB::as_map`]
+impl<S: AsRef<[u8]>> ser::Serialize for CQDB<'_, S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        ser::Serialize::serialize(&self.as_map(KeyFormat::Auto), serializer)
+    }
+}
+
+/// Escape backslashes and invalid UTF-8 bytes
+fn escape(key: &[u8]) -> String {
+    use fmt::Write;
+
+    let mut escaped = String::with_capacity(key.len());
+    for chunk in key.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '\\' {
+                escaped.push_str("\\\\");
+            } else {
+                escaped.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            // Writing to a String never fails
+            let _ = write!(escaped, "\\x{:02x}", byte);
+        }
+    }
+    escaped
+}
+
+/// Reverse [`escape`], `None` on a malformed escape sequence
+fn unescape(key: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(key.len());
+    let mut rest = key.as_bytes();
+    while let Some(pos) = rest.find_byte(b'\\') {
+        bytes.extend_from_slice(&rest[..pos]);
+        match rest.get(pos + 1)? {
+            b'\\' => {
+                bytes.push(b'\\');
+                rest = &rest[pos + 2..];
+            }
+            b'x' => {
+                let hex = core::str::from_utf8(rest.get(pos + 2..pos + 4)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &rest[pos + 4..];
+            }
+            _ => return None,
+        }
+    }
+    bytes.extend_from_slice(rest);
+    Some(bytes)
+}
+
+/// A key deserialized from a string, bytes or a sequence of bytes
+struct KeySeed(KeyFormat);
+
+impl<'de> de::DeserializeSeed<'de> for KeySeed {
+    type Value = Vec<u8>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeySeed {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match self.0 {
+            KeyFormat::Escaped => unescape(v)
+                .ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &"an escaped key")),
+            _ => Ok(v.as_bytes().to_vec()),
+        }
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+/// An `(id, key)` pair
+struct PairSeed(KeyFormat);
+
+impl<'de> de::DeserializeSeed<'de> for PairSeed {
+    type Value = (u32, Vec<u8>);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de> Visitor<'de> for PairSeed {
+    type Value = (u32, Vec<u8>);
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an (id, key) pair")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let id = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let key = seq
+            .next_element_seed(KeySeed(self.0))?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok((id, key))
+    }
+}
+
+/// Puts every entry of a map or sequence of pairs into a writer
+struct EntriesVisitor<'w, T: Sink> {
+    writer: &'w mut CQDBWriter<T>,
+    keys: KeyFormat,
+}
+
+impl<'de, T: Sink> Visitor<'de> for EntriesVisitor<'_, T> {
+    type Value = u32;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of key to id or a sequence of (id, key) pairs")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut num = 0;
+        while let Some(key) = map.next_key_seed(KeySeed(self.keys))? {
+            let id = map.next_value()?;
+            self.writer.put(key, id).map_err(de::Error::custom)?;
+            num += 1;
+        }
+        Ok(num)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut num = 0;
+        while let Some((id, key)) = seq.next_element_seed(PairSeed(self.keys))? {
+            self.writer.put(key, id).map_err(de::Error::custom)?;
+            num += 1;
+        }
+        Ok(num)
+    }
+}
+
+impl<T: Sink> CQDBWriter<T> {
+    /// Put every association of a serialized map of key to id, or sequence of
+    /// `(id, key)` pairs, to the database
+    ///
+    /// Keys may be strings, interpreted according to `keys`, bytes or sequences of bytes.
+    /// The format must be self-describing, like JSON. Returns the number of associations put.
+    pub fn put_deserialized<'de, D: Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+        keys: KeyFormat,
+    ) -> Result<u32, D::Error> {
+        deserializer.deserialize_any(EntriesVisitor { writer: self, keys })
+    }
+}
@@ -0,0 +1,165 @@
+//! Output streams for [`CQDBWriter`](crate::CQDBWriter)
+use alloc::vec::Vec;
+
+use crate::Error;
+
+/// A seekable byte sink that a [`CQDBWriter`](crate::CQDBWriter) writes a database into
+///
+/// With the `std` feature it is implemented for every [`Write`](std::io::Write) +
+/// [`Seek`](std::io::Seek) stream. Without it, use a [`BufSink`] over a `Vec<u8>`
+/// or a `&mut [u8]`.
+pub trait Sink {
+    /// Current position in the sink
+    fn position(&mut self) -> Result<u64, Error>;
+
+    /// Move to an absolute position, which may be past the end of the data written so far
+    ///
+    /// The writer skips the header this way before writing the records. A later
+    /// write past the end fills the gap with zeros, like a file or [`BufSink`].
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error>;
+
+    /// Write the whole buffer at the current position
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error>;
+
+    /// Flush buffered data to the underlying storage
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: std::io::Write + std::io::Seek> Sink for T {
+    #[inline]
+    fn position(&mut self) -> Result<u64, Error> {
+        Ok(self.stream_position()?)
+    }
+
+    #[inline]
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error> {
+        self.seek(std::io::SeekFrom::Start(pos))?;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        Ok(std::io::Write::write_all(self, buf)?)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(std::io::Write::flush(self)?)
+    }
+}
+
+/// An in-memory [`Sink`] over a `Vec<u8>` or `&mut Vec<u8>`, which grow as needed, or a fixed `&mut [u8]`
+///
+/// Writing past the end of a fixed buffer fails with [`Error::BufferFull`].
+#[derive(Debug, Clone, Default)]
+pub struct BufSink<B> {
+    buf: B,
+    pos: usize,
+}
+
+impl<B> BufSink<B> {
+    /// Create a sink writing from the start of `buf`
+    pub fn new(buf: B) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Get a reference to the underlying buffer
+    pub fn get_ref(&self) -> &B {
+        &self.buf
+    }
+
+    /// Consume the sink, returning the underlying buffer
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+}
+
+impl<'a> BufSink<&'a mut Vec<u8>> {
+    /// Create a sink appending to the end of `buf`
+    pub fn append(buf: &'a mut Vec<u8>) -> Self {
+        let pos = buf.len();
+        Self { buf, pos }
+    }
+}
+
+#[cfg(feature = "std")]
+impl BufSink<Vec<u8>> {
+    /// Insert `len` zero bytes at `at`, moving the position along if it is not before `at`
+    pub(crate) fn insert_zeros(&mut self, at: usize, len: usize) {
+        if at < self.buf.len() {
+            self.buf.splice(at..at, core::iter::repeat_n(0, len));
+        }
+        if self.pos >= at {
+            self.pos += len;
+        }
+    }
+}
+
+impl Sink for BufSink<Vec<u8>> {
+    #[inline]
+    fn position(&mut self) -> Result<u64, Error> {
+        Ok(self.pos as u64)
+    }
+
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error> {
+        self.pos = usize::try_from(pos).map_err(|_| Error::BufferFull)?;
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        write_vec(&mut self.buf, &mut self.pos, buf)
+    }
+}
+
+impl Sink for BufSink<&mut Vec<u8>> {
+    #[inline]
+    fn position(&mut self) -> Result<u64, Error> {
+        Ok(self.pos as u64)
+    }
+
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error> {
+        self.pos = usize::try_from(pos).map_err(|_| Error::BufferFull)?;
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        write_vec(self.buf, &mut self.pos, buf)
+    }
+}
+
+/// Write `buf` at `*pos` in `vec`, growing it as needed
+fn write_vec(vec: &mut Vec<u8>, pos: &mut usize, buf: &[u8]) -> Result<(), Error> {
+    let end = pos.checked_add(buf.len()).ok_or(Error::BufferFull)?;
+    if vec.len() < end {
+        vec.resize(end, 0);
+    }
+    vec[*pos..end].copy_from_slice(buf);
+    *pos = end;
+    Ok(())
+}
+
+impl Sink for BufSink<&mut [u8]> {
+    #[inline]
+    fn position(&mut self) -> Result<u64, Error> {
+        Ok(self.pos as u64)
+    }
+
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error> {
+        self.pos = usize::try_from(pos).map_err(|_| Error::BufferFull)?;
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let dst = self
+            .pos
+            .checked_add(buf.len())
+            .and_then(|end| self.buf.get_mut(self.pos..end))
+            .ok_or(Error::BufferFull)?;
+        dst.copy_from_slice(buf);
+        self.pos += buf.len();
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+//! Sorted key index for prefix and range queries
+//!
+//! The index is an extension section holding the offsets of all records,
+//! sorted by key bytes. It is written when the [`Flag::SORTED_INDEX`] flag is set.
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::{iter::FusedIterator, ops::Bound};
+
+use bstr::{BStr, ByteSlice};
+
+#[cfg(feature = "alloc")]
+use crate::Sink;
+use crate::{ByteOrder, Error, Flag, Format, Layout, read_record, section};
+
+/// Section tag of the sorted key index
+pub(crate) const SORTED_INDEX_TAG: &[u8; 4] = b"SIDX";
+
+/// Location of the sorted key index in the buffer
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SortedIndex {
+    /// Offset of the sorted record offset array
+    offset: usize,
+    /// Number of records in the index
+    num: usize,
+    /// Byte order of the database
+    order: ByteOrder,
+    /// Width of the record offsets
+    format: Format,
+}
+
+impl SortedIndex {
+    /// Locate the index among the extension sections of a parsed layout
+    pub(crate) fn find(layout: &Layout, buffer: &[u8]) -> Option<Self> {
+        if layout.header.flag & Flag::SORTED_INDEX.bits() == 0 {
+            return None;
+        }
+        let payload = section::find(
+            buffer,
+            layout.sections_offset,
+            layout.header.size as usize,
+            SORTED_INDEX_TAG,
+            layout.order,
+            layout.format,
+        )?;
+        Some(Self {
+            offset: payload.start,
+            num: payload.len() / layout.format.offset_size(),
+            order: layout.order,
+            format: layout.format,
+        })
+    }
+
+    /// Key of the `i`-th record in sorted order, empty if the record is corrupt
+    #[inline]
+    fn key<'a>(&self, buffer: &'a [u8], i: usize) -> &'a [u8] {
+        // Index read is safe: section bounds validated in new()
+        let pos = self.offset + i * self.format.offset_size();
+        let offset = self.format.read_offset(self.order, buffer, pos) as usize;
+        read_record(buffer, offset, self.order).map_or(&[], |(_, key)| key)
+    }
+
+    /// Index of the first key for which `pred` is false, keys must be partitioned by `pred`
+    fn partition_point(&self, buffer: &[u8], mut pred: impl FnMut(&[u8]) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, self.num);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pred(self.key(buffer, mid)) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    pub(crate) fn prefix<'a>(&self, buffer: &'a [u8], prefix: &[u8]) -> SortedIter<'a> {
+        let start = self.partition_point(buffer, |key| key < prefix);
+        let end = self.partition_point(buffer, |key| key < prefix || key.starts_with(prefix));
+        self.iter(buffer, start, end)
+    }
+
+    pub(crate) fn range<'a>(
+        &self,
+        buffer: &'a [u8],
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> SortedIter<'a> {
+        let start = match start {
+            Bound::Included(bound) => self.partition_point(buffer, |key| key < bound),
+            Bound::Excluded(bound) => self.partition_point(buffer, |key| key <= bound),
+            Bound::Unbounded => 0,
+        };
+        let end = match end {
+            Bound::Included(bound) => self.partition_point(buffer, |key| key <= bound),
+            Bound::Excluded(bound) => self.partition_point(buffer, |key| key < bound),
+            Bound::Unbounded => self.num,
+        };
+        self.iter(buffer, start, end.max(start))
+    }
+
+    fn iter<'a>(&self, buffer: &'a [u8], start: usize, end: usize) -> SortedIter<'a> {
+        SortedIter {
+            buffer,
+            offset: self.offset,
+            front: start,
+            back: end,
+            order: self.order,
+            format: self.format,
+        }
+    }
+}
+
+/// Iterator over key, id pairs in lexicographic key order,
+/// see [`CQDB::prefix`](crate::CQDB::prefix) and [`CQDB::range`](crate::CQDB::range)
+pub struct SortedIter<'a> {
+    buffer: &'a [u8],
+    /// Offset of the sorted record offset array
+    offset: usize,
+    /// Next position from the front
+    front: usize,
+    /// One past the next position from the back
+    back: usize,
+    order: ByteOrder,
+    format: Format,
+}
+
+impl<'a> SortedIter<'a> {
+    #[inline]
+    fn get(&self, i: usize) -> Result<(&'a BStr, u32), Error> {
+        let pos = self.offset + i * self.format.offset_size();
+        let offset = self.format.read_offset(self.order, self.buffer, pos) as usize;
+        let (id, key) = read_record(self.buffer, offset, self.order)?;
+        Ok((key.as_bstr(), id))
+    }
+}
+
+impl<'a> Iterator for SortedIter<'a> {
+    type Item = Result<(&'a BStr, u32), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.front += 1;
+        Some(self.get(self.front - 1))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.front = self.front.saturating_add(n).min(self.back);
+        self.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.back - self.front;
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for SortedIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.get(self.back))
+    }
+}
+
+impl ExactSizeIterator for SortedIter<'_> {}
+
+impl FusedIterator for SortedIter<'_> {}
+
+/// Keys collected by the writer to build the sorted index
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default)]
+pub(crate) struct SortedKeys {
+    /// Concatenated key bytes
+    keys: Vec<u8>,
+    /// `(start, end)` of each key in `keys` and the offset of its record
+    entries: Vec<(usize, usize, u64)>,
+}
+
+#[cfg(feature = "alloc")]
+impl SortedKeys {
+    pub(crate) fn push(&mut self, key: &[u8], offset: u64) {
+        let start = self.keys.len();
+        self.keys.extend_from_slice(key);
+        self.entries.push((start, self.keys.len(), offset));
+    }
+
+    /// Move every record offset by `by`
+    #[cfg(feature = "std")]
+    pub(crate) fn shift(&mut self, by: u64) {
+        for (_, _, offset) in &mut self.entries {
+            *offset += by;
+        }
+    }
+
+    /// Write the sorted index section
+    pub(crate) fn write<W: Sink>(
+        &mut self,
+        writer: &mut W,
+        order: ByteOrder,
+        format: Format,
+    ) -> Result<(), Error> {
+        let keys = &self.keys;
+        self.entries
+            .sort_by(|a, b| keys[a.0..a.1].cmp(&keys[b.0..b.1]));
+        let size = self.entries.len() * format.offset_size();
+        section::write_header(writer, SORTED_INDEX_TAG, size as u64, order, format)?;
+        let mut buf = Vec::with_capacity(size);
+        for &(_, _, offset) in &self.entries {
+            format.push_offset(order, offset, &mut buf);
+        }
+        writer.write_all(&buf)
+    }
+}
@@ -0,0 +1,154 @@
+//! Database statistics and shape report
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
+
+use crate::{Flag, Format, KeyHash, Layout, NUM_TABLES, read_record};
+
+/// Statistics about the shape of a database, see [`CQDB::stats`](crate::CQDB::stats)
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// On-disk format
+    pub format: Format,
+    /// Global flags
+    pub flag: Flag,
+    /// Chunk size including the header
+    pub size: u64,
+    /// Number of elements in the backward link array
+    pub bwd_size: u32,
+    /// Offset to the backward link array, 0 if there is none
+    pub bwd_offset: u64,
+    /// Number of records in each of the 256 hash tables
+    pub table_records: Vec<u32>,
+    /// Total number of buckets over all hash tables
+    pub buckets: u64,
+    /// Number of occupied buckets
+    pub occupied: u64,
+    /// Average number of buckets probed to find a stored key
+    pub avg_probe_len: f64,
+    /// Maximum number of buckets probed to find a stored key
+    pub max_probe_len: u32,
+    /// Number of keys of each length in bytes, excluding the NUL terminator
+    pub key_lengths: BTreeMap<usize, u32>,
+    /// Bytes used by the chunk header and table references
+    pub header_bytes: usize,
+    /// Bytes used by the records
+    pub record_bytes: usize,
+    /// Bytes used by the hash tables
+    pub table_bytes: usize,
+    /// Bytes used by the backward link array
+    pub bwd_bytes: usize,
+    /// Bytes used by extension sections
+    pub section_bytes: usize,
+}
+
+impl Stats {
+    /// Fraction of buckets that are occupied
+    pub fn occupancy(&self) -> f64 {
+        if self.buckets == 0 {
+            0.0
+        } else {
+            self.occupied as f64 / self.buckets as f64
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "format: {:?}", self.format)?;
+        writeln!(f, "flag: {:?}", self.flag)?;
+        writeln!(f, "size: {} bytes", self.size)?;
+        writeln!(f, "bwd_size: {}", self.bwd_size)?;
+        writeln!(f, "bwd_offset: {}", self.bwd_offset)?;
+        let min = self.table_records.iter().min().copied().unwrap_or(0);
+        let max = self.table_records.iter().max().copied().unwrap_or(0);
+        writeln!(f, "records per table: min {}, max {}", min, max)?;
+        writeln!(
+            f,
+            "buckets: {} occupied of {} ({:.1}%)",
+            self.occupied,
+            self.buckets,
+            self.occupancy() * 100.0
+        )?;
+        writeln!(
+            f,
+            "probe length: avg {:.3}, max {}",
+            self.avg_probe_len, self.max_probe_len
+        )?;
+        writeln!(f, "key lengths:")?;
+        for (len, count) in &self.key_lengths {
+            writeln!(f, "  {:>6}: {}", len, count)?;
+        }
+        write!(
+            f,
+            "bytes: header {}, records {}, tables {}, bwd {}, sections {}",
+            self.header_bytes,
+            self.record_bytes,
+            self.table_bytes,
+            self.bwd_bytes,
+            self.section_bytes
+        )
+    }
+}
+
+impl Layout {
+    pub(crate) fn stats(&self, buffer: &[u8]) -> Stats {
+        let header_bytes = self.format.header_size();
+        let mut table_records = vec![0; NUM_TABLES];
+        let mut buckets = 0u64;
+        let mut occupied = 0u64;
+        let mut probe_total = 0u64;
+        let mut max_probe_len = 0;
+        let mut key_lengths = BTreeMap::new();
+        for (i, table) in self.tables.iter().enumerate() {
+            let n = table.num;
+            buckets += u64::from(n);
+            for k in 0..n {
+                // Bucket reads are safe: table bounds validated in new()
+                let (hash, offset) = self.bucket(buffer, table.offset, k);
+                if offset == 0 {
+                    continue;
+                }
+                occupied += 1;
+                table_records[i] += 1;
+                // Distance from the home bucket, wrapping around the table
+                let home = KeyHash::from_raw(hash).bucket(n);
+                let probe_len = (k + n - home) % n + 1;
+                probe_total += u64::from(probe_len);
+                max_probe_len = max_probe_len.max(probe_len);
+                if let Ok((_, key)) = read_record(buffer, offset as usize, self.order) {
+                    *key_lengths.entry(key.len()).or_default() += 1;
+                }
+            }
+        }
+        let table_bytes = buckets as usize * self.format.bucket_size();
+        let bwd_bytes = if self.bwd_offset > 0 {
+            self.header.bwd_size as usize * self.format.offset_size()
+        } else {
+            0
+        };
+        let records_end = self.records_end(buffer.len());
+        Stats {
+            format: self.format,
+            flag: Flag::from_bits_retain(self.header.flag),
+            size: self.header.size,
+            bwd_size: self.header.bwd_size,
+            bwd_offset: self.header.bwd_offset,
+            table_records,
+            buckets,
+            occupied,
+            avg_probe_len: if occupied == 0 {
+                0.0
+            } else {
+                probe_total as f64 / occupied as f64
+            },
+            max_probe_len,
+            key_lengths,
+            header_bytes,
+            record_bytes: records_end.saturating_sub(header_bytes),
+            table_bytes,
+            bwd_bytes,
+            section_bytes: (self.header.size as usize).saturating_sub(self.sections_offset),
+        }
+    }
+}
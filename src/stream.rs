@@ -0,0 +1,211 @@
+//! Writer for non-seekable streams
+use std::{fmt, io::Write, sync::Arc};
+
+use crate::{BufSink, ByteOrder, CQDBWriter, DuplicatePolicy, Error, Flag, Format, KeyNormalizer};
+
+/// Writer for a constant quark database on a forward-only stream
+///
+/// [`CQDBWriter`] seeks back to write the header, which pipes, sockets and
+/// compressors do not support. This writer builds the database in memory
+/// and writes it to `W` in a single forward pass when finished. The output is
+/// byte-identical to what [`CQDBWriter`] produces for the same associations.
+///
+/// Unless a format is set with [`CQDBStreamWriter::with_format`], the database
+/// is written in [`Format::Standard`] and switches to [`Format::Wide`] once it
+/// outgrows 4 GiB.
+pub struct CQDBStreamWriter<W: Write> {
+    /// In-memory database
+    db: CQDBWriter<BufSink<Vec<u8>>>,
+    /// Output stream, `None` once finished
+    writer: Option<W>,
+    /// Whether to widen the format when the database outgrows it
+    auto: bool,
+}
+
+impl<W: Write> fmt::Debug for CQDBStreamWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CQDBStreamWriter")
+            .field("flag", &self.db.flag)
+            .field("order", &self.db.order)
+            .field("format", &self.db.format)
+            .field("current", &self.db.current)
+            .finish()
+    }
+}
+
+impl<W: Write> CQDBStreamWriter<W> {
+    /// Create a new CQDB stream writer
+    pub fn new(writer: W) -> Self {
+        Self::with_flag(writer, Flag::NONE)
+    }
+
+    /// Create a new CQDB stream writer with flag
+    pub fn with_flag(writer: W, flag: Flag) -> Self {
+        Self::with_byte_order(writer, flag, ByteOrder::LittleEndian)
+    }
+
+    /// Create a new CQDB stream writer with flag, writing integers in `order`
+    pub fn with_byte_order(writer: W, flag: Flag, order: ByteOrder) -> Self {
+        // Seeking in a Vec past its end never fails
+        let db = CQDBWriter::with_byte_order(BufSink::default(), flag, order)
+            .expect("in-memory sink cannot fail");
+        Self {
+            db,
+            writer: Some(writer),
+            auto: true,
+        }
+    }
+
+    /// Write the database in `format` instead of picking it by size
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBStreamWriter::put`].
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.db
+            .set_format(format)
+            .expect("in-memory sink cannot fail");
+        self.auto = false;
+        self
+    }
+
+    /// Normalize keys with `normalizer`, see [`CQDBWriter::with_normalizer`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBStreamWriter::put`].
+    pub fn with_normalizer<N: KeyNormalizer + 'static>(mut self, normalizer: N) -> Self {
+        self.db.set_normalizer(Arc::new(normalizer));
+        self
+    }
+
+    /// Handle keys and ids that were already put according to `policy`,
+    /// see [`CQDBWriter::with_duplicates`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after [`CQDBStreamWriter::put`].
+    pub fn with_duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.db.set_duplicates(policy);
+        self
+    }
+
+    /// Put a string/identifier association to the database
+    pub fn put<K: AsRef<[u8]>>(&mut self, key: K, id: u32) -> Result<(), Error> {
+        let key = key.as_ref();
+        match self.db.put(key, id) {
+            Err(Error::TooLarge { .. }) if self.can_widen() => {
+                self.widen();
+                self.db.put(key, id)
+            }
+            result => result,
+        }
+    }
+
+    fn can_widen(&self) -> bool {
+        self.auto && self.db.format == Format::Standard
+    }
+
+    /// Switch the database written so far to [`Format::Wide`]
+    ///
+    /// Records move past the larger header, so every stored offset shifts by
+    /// the difference in header size.
+    fn widen(&mut self) {
+        let (from, to) = (Format::Standard.header_size(), Format::Wide.header_size());
+        let shift = (to - from) as u64;
+        let db = &mut self.db;
+        let sink = db.writer.as_mut().expect("writer is finished");
+        sink.insert_zeros(from, to - from);
+        for table in &mut db.tables {
+            table.offsets.widen(shift);
+        }
+        db.bwd.widen(shift);
+        if let Some(sorted) = &mut db.sorted {
+            sorted.shift(shift);
+        }
+        db.current += shift;
+        db.format = Format::Wide;
+    }
+
+    /// Write the whole database to the stream and flush it, returning the stream
+    ///
+    /// Dropping the writer also writes the database, but silently ignores errors.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.finish_mut()
+    }
+
+    fn finish_mut(&mut self) -> Result<W, Error> {
+        let mut writer = self.writer.take().expect("writer is finished");
+        // Write the associations buffered for the duplicate policy here, so
+        // that they can widen the format too
+        let pending = self.db.dedup.as_mut().map(|dedup| dedup.take_pending());
+        for (key, id) in pending.unwrap_or_default() {
+            let (key_size, hash) = match self.db.check_record(&key, id) {
+                Err(Error::TooLarge { .. }) if self.can_widen() => {
+                    self.widen();
+                    self.db.check_record(&key, id)?
+                }
+                result => result?,
+            };
+            self.db.write_record(&key, key_size, hash, id)?;
+        }
+        let buf = self.db.finish_mut()?.into_inner();
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Drop for CQDBStreamWriter<W> {
+    /// Finish the database if [`CQDBStreamWriter::finish`] was not called, ignoring errors
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.finish_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CQDBStreamWriter;
+    use crate::{BufSink, CQDBWriter, DuplicatePolicy, Error, Flag, Format};
+
+    #[test]
+    fn test_widen_matches_wide_writer() {
+        // Sparse ids and a repeated key, with the last put after widening
+        let pairs: Vec<_> = (0..50u32)
+            .map(|i| (format!("{:08}", i), i * 3))
+            .chain([("00000007".to_string(), 200)])
+            .collect();
+        let (before, after) = pairs.split_at(30);
+        for policy in [DuplicatePolicy::Allow, DuplicatePolicy::KeepLast] {
+            let mut stream =
+                CQDBStreamWriter::with_flag(Vec::new(), Flag::SORTED_INDEX).with_duplicates(policy);
+            let mut wide = CQDBWriter::with_flag(BufSink::<Vec<u8>>::default(), Flag::SORTED_INDEX)
+                .unwrap()
+                .with_format(Format::Wide)
+                .unwrap()
+                .with_duplicates(policy);
+            for (key, id) in before {
+                stream.put(key, *id).unwrap();
+                wide.put(key, *id).unwrap();
+            }
+            stream.widen();
+            for (key, id) in after {
+                stream.put(key, *id).unwrap();
+                wide.put(key, *id).unwrap();
+            }
+            let expected = wide.finish().unwrap().into_inner();
+            assert_eq!(stream.finish().unwrap(), expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_too_many_records() {
+        let mut stream = CQDBStreamWriter::new(Vec::new());
+        stream.db.num = u32::MAX;
+        // So many records outgrow the standard format first
+        assert!(matches!(stream.put("key", 0), Err(Error::TooManyRecords)));
+        assert_eq!(stream.db.format, Format::Wide);
+    }
+}
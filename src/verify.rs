@@ -0,0 +1,305 @@
+//! Full integrity check of a database
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{ByteOrder, KeyHash, Layout, NUM_TABLES, section};
+
+/// A problem found by [`CQDB::verify`](crate::CQDB::verify)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Issue {
+    /// The chunk size in the header does not match the end of its data
+    SizeMismatch {
+        /// Chunk size stored in the header
+        header: u64,
+        /// End of the hash tables, backward link array and extension sections
+        actual: usize,
+    },
+    /// A bucket points at a record outside of the buffer
+    RecordOutOfBounds {
+        /// Index of the hash table
+        table: usize,
+        /// Index of the bucket in the table
+        bucket: u32,
+        /// Offset of the record
+        offset: u64,
+    },
+    /// A record key is not NUL-terminated
+    MissingNul {
+        /// Index of the hash table
+        table: usize,
+        /// Index of the bucket in the table
+        bucket: u32,
+        /// Offset of the record
+        offset: u64,
+    },
+    /// The hash stored in a bucket is not the hash of the record key
+    HashMismatch {
+        /// Index of the hash table
+        table: usize,
+        /// Index of the bucket in the table
+        bucket: u32,
+        /// Hash stored in the bucket
+        stored: u32,
+        /// Hash computed from the record key
+        computed: u32,
+    },
+    /// A record is stored in a table other than the one its hash selects
+    WrongTable {
+        /// Index of the hash table
+        table: usize,
+        /// Index of the bucket in the table
+        bucket: u32,
+        /// Index of the table selected by the hash
+        expected: usize,
+    },
+    /// A record cannot be found by probing from its hash
+    Unreachable {
+        /// Index of the hash table
+        table: usize,
+        /// Index of the bucket in the table
+        bucket: u32,
+    },
+    /// A backward link points at a record outside of the buffer
+    BackwardLinkOutOfBounds {
+        /// Identifier of the backward link
+        id: u32,
+        /// Offset of the record
+        offset: u64,
+    },
+    /// A backward link points at a record with a different identifier
+    BackwardLinkMismatch {
+        /// Identifier of the backward link
+        id: u32,
+        /// Offset of the record
+        offset: u64,
+        /// Identifier stored in the record
+        found: u32,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::SizeMismatch { header, actual } => write!(
+                f,
+                "chunk size {} in header does not match end of data {}",
+                header, actual
+            ),
+            Issue::RecordOutOfBounds {
+                table,
+                bucket,
+                offset,
+            } => write!(
+                f,
+                "table {} bucket {}: record at offset {} is out of bounds",
+                table, bucket, offset
+            ),
+            Issue::MissingNul {
+                table,
+                bucket,
+                offset,
+            } => write!(
+                f,
+                "table {} bucket {}: record at offset {} is not NUL-terminated",
+                table, bucket, offset
+            ),
+            Issue::HashMismatch {
+                table,
+                bucket,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "table {} bucket {}: stored hash {:#010x} does not match key hash {:#010x}",
+                table, bucket, stored, computed
+            ),
+            Issue::WrongTable {
+                table,
+                bucket,
+                expected,
+            } => write!(
+                f,
+                "table {} bucket {}: key belongs to table {}",
+                table, bucket, expected
+            ),
+            Issue::Unreachable { table, bucket } => write!(
+                f,
+                "table {} bucket {}: key is not reachable by probing",
+                table, bucket
+            ),
+            Issue::BackwardLinkOutOfBounds { id, offset } => write!(
+                f,
+                "backward link {}: record at offset {} is out of bounds",
+                id, offset
+            ),
+            Issue::BackwardLinkMismatch { id, offset, found } => write!(
+                f,
+                "backward link {}: record at offset {} has id {}",
+                id, offset, found
+            ),
+        }
+    }
+}
+
+/// Report of a full integrity check, see [`CQDB::verify`](crate::CQDB::verify)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no problem was found
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// All problems found, in the order they were encountered
+    #[inline]
+    pub fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.issues.is_empty() {
+            return f.write_str("ok");
+        }
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            issue.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a record could not be parsed
+enum RecordFault {
+    OutOfBounds,
+    MissingNul,
+}
+
+/// Parse the record at `offset`, returning its id and key without the NUL terminator
+fn parse_record(buffer: &[u8], offset: u64, order: ByteOrder) -> Result<(u32, &[u8]), RecordFault> {
+    let start = usize::try_from(offset).map_err(|_| RecordFault::OutOfBounds)?;
+    let rec = start
+        .checked_add(8)
+        .and_then(|end| buffer.get(start..end))
+        .ok_or(RecordFault::OutOfBounds)?;
+    let id = order.u32_from([rec[0], rec[1], rec[2], rec[3]]);
+    let ksize = order.u32_from([rec[4], rec[5], rec[6], rec[7]]) as usize;
+    let data = (start + 8)
+        .checked_add(ksize)
+        .and_then(|end| buffer.get(start + 8..end))
+        .ok_or(RecordFault::OutOfBounds)?;
+    match data.split_last() {
+        Some((0, key)) => Ok((id, key)),
+        _ => Err(RecordFault::MissingNul),
+    }
+}
+
+impl Layout {
+    /// Probe `table` for `key` like a lookup does and return the bucket index it resolves to
+    fn probe(&self, buffer: &[u8], table: usize, hash: u32, key: &[u8]) -> Option<u32> {
+        let n = self.tables[table].num;
+        let mut k = (hash >> 8) % n;
+        // Bounded by the table size so a table without a vacant bucket terminates
+        for _ in 0..n {
+            let (bucket_hash, offset) = self.bucket(buffer, self.tables[table].offset, k);
+            if offset == 0 {
+                break;
+            }
+            if bucket_hash == hash
+                && parse_record(buffer, offset, self.order).is_ok_and(|(_, found)| found == key)
+            {
+                return Some(k);
+            }
+            k = (k + 1) % n;
+        }
+        None
+    }
+
+    pub(crate) fn verify(&self, buffer: &[u8]) -> VerifyReport {
+        let mut issues = Vec::new();
+        let end = section::end(
+            buffer,
+            self.sections_offset,
+            self.header.size as usize,
+            self.order,
+            self.format,
+        );
+        if self.header.size as usize != end {
+            issues.push(Issue::SizeMismatch {
+                header: self.header.size,
+                actual: end,
+            });
+        }
+        for table in 0..NUM_TABLES {
+            for bucket in 0..self.tables[table].num {
+                // Bucket reads are safe: table bounds validated in new()
+                let (stored, offset) = self.bucket(buffer, self.tables[table].offset, bucket);
+                if offset == 0 {
+                    continue;
+                }
+                let key = match parse_record(buffer, offset, self.order) {
+                    Ok((_, key)) => key,
+                    Err(RecordFault::MissingNul) => {
+                        issues.push(Issue::MissingNul {
+                            table,
+                            bucket,
+                            offset,
+                        });
+                        continue;
+                    }
+                    Err(RecordFault::OutOfBounds) => {
+                        issues.push(Issue::RecordOutOfBounds {
+                            table,
+                            bucket,
+                            offset,
+                        });
+                        continue;
+                    }
+                };
+                let computed = KeyHash::new(key).value();
+                if stored != computed {
+                    issues.push(Issue::HashMismatch {
+                        table,
+                        bucket,
+                        stored,
+                        computed,
+                    });
+                }
+                let expected = computed as usize % NUM_TABLES;
+                if expected != table {
+                    issues.push(Issue::WrongTable {
+                        table,
+                        bucket,
+                        expected,
+                    });
+                } else if self.probe(buffer, table, computed, key) != Some(bucket) {
+                    issues.push(Issue::Unreachable { table, bucket });
+                }
+            }
+        }
+        if self.bwd_offset > 0 {
+            for id in 0..self.header.bwd_size {
+                let offset = self.link(buffer, id);
+                if offset == 0 {
+                    continue;
+                }
+                match parse_record(buffer, offset, self.order) {
+                    Ok((found, _)) if found != id => {
+                        issues.push(Issue::BackwardLinkMismatch { id, offset, found })
+                    }
+                    Ok(_) => {}
+                    Err(_) => issues.push(Issue::BackwardLinkOutOfBounds { id, offset }),
+                }
+            }
+        }
+        VerifyReport { issues }
+    }
+}
@@ -0,0 +1,107 @@
+#!/usr/bin/env python3
+"""Write CQDB fixtures independently of the crate's writer.
+
+This follows the C writer in cqdb.c and hashes keys with lookup3's hashlittle,
+so the fixtures check the crate against the reference format rather than
+against itself.
+
+    python3 gen_fixture.py little test.cqdb
+    python3 gen_fixture.py big test_be.cqdb
+"""
+import struct
+import sys
+
+MASK = 0xFFFFFFFF
+
+
+def rot(x, k):
+    return ((x << k) | (x >> (32 - k))) & MASK
+
+
+def mix(a, b, c):
+    a = (a - c) & MASK; a ^= rot(c, 4); c = (c + b) & MASK
+    b = (b - a) & MASK; b ^= rot(a, 6); a = (a + c) & MASK
+    c = (c - b) & MASK; c ^= rot(b, 8); b = (b + a) & MASK
+    a = (a - c) & MASK; a ^= rot(c, 16); c = (c + b) & MASK
+    b = (b - a) & MASK; b ^= rot(a, 19); a = (a + c) & MASK
+    c = (c - b) & MASK; c ^= rot(b, 4); b = (b + a) & MASK
+    return a, b, c
+
+
+def final(a, b, c):
+    c ^= b; c = (c - rot(b, 14)) & MASK
+    a ^= c; a = (a - rot(c, 11)) & MASK
+    b ^= a; b = (b - rot(a, 25)) & MASK
+    c ^= b; c = (c - rot(b, 16)) & MASK
+    a ^= c; a = (a - rot(c, 4)) & MASK
+    b ^= a; b = (b - rot(a, 14)) & MASK
+    c ^= b; c = (c - rot(b, 24)) & MASK
+    return c
+
+
+def hashlittle(key, initval=0):
+    a = b = c = (0xDEADBEEF + len(key) + initval) & MASK
+    while len(key) > 12:
+        a = (a + int.from_bytes(key[0:4], "little")) & MASK
+        b = (b + int.from_bytes(key[4:8], "little")) & MASK
+        c = (c + int.from_bytes(key[8:12], "little")) & MASK
+        a, b, c = mix(a, b, c)
+        key = key[12:]
+    if not key:
+        return c
+    tail = key.ljust(12, b"\0")
+    a = (a + int.from_bytes(tail[0:4], "little")) & MASK
+    b = (b + int.from_bytes(tail[4:8], "little")) & MASK
+    c = (c + int.from_bytes(tail[8:12], "little")) & MASK
+    return final(a, b, c)
+
+
+def write(pairs, endian):
+    u32 = lambda v: struct.pack(("<" if endian == "little" else ">") + "I", v)
+    data = bytearray(b"\0" * (24 + 8 * 256))
+    tables = [[] for _ in range(256)]
+    bwd = []
+    for key, id in pairs:
+        ksize = len(key) + 1
+        hv = hashlittle(key + b"\0")
+        tables[hv % 256].append((hv, len(data)))
+        if len(bwd) <= id:
+            bwd.extend([0] * (id + 1 - len(bwd)))
+        bwd[id] = len(data)
+        data += u32(id) + u32(ksize) + key + b"\0"
+    refs = []
+    for table in tables:
+        n = len(table) * 2
+        refs.append((len(data) if table else 0, n))
+        dst = [(0, 0)] * n
+        for hv, offset in table:
+            k = (hv >> 8) % n
+            while dst[k][1] != 0:
+                k = (k + 1) % n
+            dst[k] = (hv, offset)
+        for hv, offset in dst:
+            data += u32(hv) + u32(offset)
+    bwd_offset = len(data)
+    for offset in bwd:
+        data += u32(offset)
+    header = b"CQDB" + u32(len(data)) + u32(0) + u32(0x62445371)
+    header += u32(len(bwd)) + u32(bwd_offset)
+    header += b"".join(u32(offset) + u32(num) for offset, num in refs)
+    data[: len(header)] = header
+    return bytes(data)
+
+
+def main():
+    endian, path = sys.argv[1], sys.argv[2]
+    if endian == "little":
+        # Same associations as the fixture written by the C library
+        pairs = [(b"%08d" % i, i) for i in range(100)]
+    else:
+        # Keys longer than 12 bytes exercise hashing of whole words
+        pairs = [(b"%08d" % i * (1 + i % 3), i) for i in range(100)]
+    with open(path, "wb") as f:
+        f.write(write(pairs, endian))
+
+
+if __name__ == "__main__":
+    main()
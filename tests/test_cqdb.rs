@@ -1,11 +1,17 @@
 use std::{
+    borrow::Cow,
     ffi::{CStr, CString},
     fs,
     io::Cursor,
+    sync::Arc,
+    thread,
 };
 
 use bstr::ByteSlice;
-use cqdb::{CQDB, CQDBWriter, Flag};
+use cqdb::{
+    AsciiLowercase, BufSink, ByteOrder, CQDB, CQDBBuilder, CQDBStreamWriter, CQDBWriter,
+    DuplicatePolicy, Error, Flag, Format, Issue, KeyHash, LazyCQDB, OwnedCQDB,
+};
 
 #[test]
 fn test_cqdb_reader() {
@@ -391,3 +397,1744 @@ fn test_num_accessor() {
     let db = CQDB::new(&buf).unwrap();
     assert_eq!(db.num(), 4);
 }
+
+#[test]
+fn test_owned_reader_storages() {
+    let buf = build_cqdb(&[("a", 0), ("b", 1), ("c", 2)], Flag::NONE);
+
+    let from_vec = CQDB::from_storage(buf.clone()).unwrap();
+    let from_box = CQDB::from_storage(buf.clone().into_boxed_slice()).unwrap();
+    let from_arc: OwnedCQDB = CQDB::from_storage(Arc::from(buf.clone())).unwrap();
+    let from_reader = OwnedCQDB::from_reader(Cursor::new(&buf)).unwrap();
+
+    assert_eq!(from_vec.iter().count(), 3);
+    assert_eq!(from_box.iter().count(), 3);
+    for db in [&from_arc, &from_reader] {
+        assert_eq!(db.num(), 3);
+        assert_eq!(db.to_id("b"), Some(1));
+        assert_eq!(db.to_str(2).unwrap(), "c");
+        assert_eq!(db.iter().count(), 3);
+    }
+    assert_eq!(from_vec.to_id("a"), Some(0));
+    assert_eq!(from_box.to_str(1).unwrap(), "b");
+}
+
+#[test]
+fn test_owned_reader_shared_across_threads() {
+    fn assert_send_sync_static<T: Send + Sync + 'static>(_: &T) {}
+
+    let buf = build_cqdb(&[("x", 0), ("y", 1)], Flag::NONE);
+    let db = OwnedCQDB::from_reader(&buf[..]).unwrap();
+    drop(buf);
+    assert_send_sync_static(&db);
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            thread::spawn(move || (db.to_id("x"), db.to_id("y")))
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), (Some(0), Some(1)));
+    }
+}
+
+#[test]
+fn test_owned_reader_invalid() {
+    assert!(OwnedCQDB::from_reader(&[0u8; 100][..]).is_err());
+    assert!(CQDB::from_storage(vec![0u8; 100]).is_err());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mmap_reader() {
+    use cqdb::Advice;
+
+    // Safety: the fixture is never modified
+    let db = unsafe { CQDB::open("tests/fixtures/test.cqdb") }.unwrap();
+    assert_eq!(100, db.num());
+    for i in 0..db.num() {
+        let s = format!("{:08}", i);
+        assert_eq!(db.to_id(&s), Some(i));
+        assert_eq!(db.to_str(i).unwrap(), s);
+    }
+    db.advise(Advice::Random).unwrap();
+
+    let db =
+        unsafe { CQDB::open_with_advice("tests/fixtures/test.cqdb", Advice::WillNeed) }.unwrap();
+    assert_eq!(db.iter().count(), 100);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mmap_reader_invalid() {
+    fs::write("tests/output/mmap-invalid.cqdb", [0u8; 100]).unwrap();
+    // Safety: the file is not modified while mapped
+    let err = unsafe { CQDB::open("tests/output/mmap-invalid.cqdb") }.unwrap_err();
+    assert!(err.to_string().contains("invalid file format"));
+    assert!(unsafe { CQDB::open("tests/output/does-not-exist.cqdb") }.is_err());
+}
+
+#[test]
+fn test_new_error_variants() {
+    assert!(matches!(CQDB::new(&[0u8; 100]), Err(Error::TooSmall)));
+
+    let mut buf = vec![0u8; 2072];
+    buf[0..4].copy_from_slice(b"XXXX");
+    assert!(matches!(CQDB::new(&buf), Err(Error::BadMagic)));
+
+    buf[0..4].copy_from_slice(b"CQDB");
+    buf[12..16].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    assert!(matches!(CQDB::new(&buf), Err(Error::ByteOrder)));
+
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    buf[16..20].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    assert!(matches!(
+        CQDB::new(&buf),
+        Err(Error::BackwardLinkOutOfBounds)
+    ));
+
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    let table = (0..256)
+        .find(|i| buf[24 + i * 8..28 + i * 8] != [0, 0, 0, 0])
+        .unwrap();
+    buf[28 + table * 8..32 + table * 8].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    match CQDB::new(&buf) {
+        Err(Error::TableOutOfBounds { table: t }) => assert_eq!(t, table),
+        other => panic!("expected TableOutOfBounds, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_error_into_io_error() {
+    let err: std::io::Error = CQDB::new(&[0u8; 100]).unwrap_err().into();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("invalid file format"));
+}
+
+#[test]
+fn test_try_lookups_corrupt_record() {
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    // The first record starts right after the header and table references
+    buf[2076..2080].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    let db = CQDB::new(&buf).unwrap();
+
+    assert!(matches!(
+        db.try_to_id("hello"),
+        Err(Error::CorruptRecord { offset: 2072 })
+    ));
+    assert!(matches!(
+        db.try_to_str(0),
+        Err(Error::CorruptRecord { offset: 2072 })
+    ));
+    assert_eq!(db.to_id("hello"), None);
+    assert_eq!(db.to_str(0), None);
+    assert!(db.iter().next().unwrap().is_err());
+}
+
+#[test]
+fn test_try_lookups_missing() {
+    let buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.try_to_id("hello").unwrap(), Some(0));
+    assert_eq!(db.try_to_id("world").unwrap(), None);
+    assert_eq!(db.try_to_str(0).unwrap().unwrap(), "hello");
+    assert_eq!(db.try_to_str(1).unwrap(), None);
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+/// Returns the table index and the buffer position of the first occupied bucket
+fn first_bucket(buf: &[u8]) -> (usize, usize) {
+    for table in 0..256 {
+        let offset = read_u32(buf, 24 + table * 8) as usize;
+        let num = read_u32(buf, 28 + table * 8) as usize;
+        for k in 0..num {
+            let pos = offset + k * 8;
+            if read_u32(buf, pos + 4) != 0 {
+                return (table, pos);
+            }
+        }
+    }
+    panic!("no occupied bucket");
+}
+
+#[test]
+fn test_verify_ok() {
+    let buf = fs::read("tests/fixtures/test.cqdb").unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    let report = db.verify();
+    assert!(report.is_ok(), "{}", report);
+
+    let keys: Vec<(String, u32)> = (0..1000).map(|i| (format!("key_{}", i), i)).collect();
+    let refs: Vec<(&str, u32)> = keys.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    for flag in [Flag::NONE, Flag::ONEWAY] {
+        let buf = build_cqdb(&refs, flag);
+        let report = CQDB::new(&buf).unwrap().verify();
+        assert!(report.is_ok(), "{}", report);
+    }
+}
+
+#[test]
+fn test_verify_reports_all_issues() {
+    let mut buf = build_cqdb(&[("hello", 0), ("world", 1)], Flag::NONE);
+    // Corrupt a bucket hash
+    let (table, pos) = first_bucket(&buf);
+    let stored = read_u32(&buf, pos) ^ 0xFF00;
+    buf[pos..pos + 4].copy_from_slice(&stored.to_le_bytes());
+    // Change the id stored in the first record
+    buf[2072..2076].copy_from_slice(&7u32.to_le_bytes());
+    // Append trailing garbage covered by the chunk size
+    buf.extend_from_slice(&[0; 4]);
+    let size = buf.len() as u32;
+    buf[4..8].copy_from_slice(&size.to_le_bytes());
+
+    let db = CQDB::new(&buf).unwrap();
+    let report = db.verify();
+    assert!(!report.is_ok());
+    let issues = report.issues();
+    assert!(issues.contains(&Issue::SizeMismatch {
+        header: buf.len() as u64,
+        actual: buf.len() - 4,
+    }));
+    assert!(issues.iter().any(|issue| matches!(
+        issue,
+        Issue::HashMismatch { table: t, stored: s, .. } if *t == table && *s == stored
+    )));
+    assert!(issues.contains(&Issue::BackwardLinkMismatch {
+        id: 0,
+        offset: 2072,
+        found: 7,
+    }));
+    assert_eq!(report.to_string().lines().count(), issues.len());
+}
+
+#[test]
+fn test_verify_record_faults() {
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    // Drop the NUL terminator of "hello"
+    buf[2072 + 8 + 5] = b'!';
+    let report = CQDB::new(&buf).unwrap().verify();
+    assert!(
+        report
+            .issues()
+            .iter()
+            .any(|issue| matches!(issue, Issue::MissingNul { offset: 2072, .. }))
+    );
+
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    buf[2076..2080].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    let report = CQDB::new(&buf).unwrap().verify();
+    assert!(
+        report
+            .issues()
+            .iter()
+            .any(|issue| matches!(issue, Issue::RecordOutOfBounds { offset: 2072, .. }))
+    );
+    assert!(report.issues().contains(&Issue::BackwardLinkOutOfBounds {
+        id: 0,
+        offset: 2072,
+    }));
+}
+
+#[test]
+fn test_to_id_bytes() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap();
+    writer.put(b"caf\xe9", 0).unwrap();
+    writer.put([0xffu8, 0x00, 0x01], 1).unwrap();
+    writer.put("utf8", 2).unwrap();
+    drop(writer);
+    let buf = buf.into_inner();
+    let db = CQDB::new(&buf).unwrap();
+
+    assert_eq!(db.to_id(b"caf\xe9"), Some(0));
+    assert_eq!(db.to_id([0xffu8, 0x00, 0x01]), Some(1));
+    assert_eq!(db.to_id(&b"utf8"[..]), Some(2));
+    assert_eq!(db.to_id("utf8"), Some(2));
+    assert_eq!(db.to_id(String::from("utf8")), Some(2));
+    assert_eq!(db.to_id(b"caf\xc3\xa9"), None);
+    assert_eq!(db.try_to_id(b"caf\xe9").unwrap(), Some(0));
+
+    for id in 0..3 {
+        assert_eq!(db.to_id(db.to_str(id).unwrap()), Some(id));
+    }
+}
+
+#[test]
+fn test_records_oneway() {
+    let keys = [("alpha", 0), ("beta", 1), ("gamma", 2), ("", 3)];
+    let buf = build_cqdb(&keys, Flag::ONEWAY);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.iter().count(), 0);
+
+    let records: Vec<_> = db.records().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len(), keys.len());
+    for (record, (key, id)) in records.iter().zip(keys) {
+        assert_eq!(record.id, id);
+        assert_eq!(record.key, key);
+        assert_eq!(db.to_id(record.key), Some(id));
+    }
+    assert_eq!(records[0].offset, 2072);
+    assert_eq!(records[1].offset, 2072 + 8 + 6);
+}
+
+#[test]
+fn test_records_match_iter() {
+    let buf = fs::read("tests/fixtures/test.cqdb").unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    let mut records: Vec<_> = db
+        .records()
+        .map(|r| {
+            let r = r.unwrap();
+            (r.id, r.key)
+        })
+        .collect();
+    records.sort();
+    let items: Vec<_> = db.iter().map(|r| r.unwrap()).collect();
+    assert_eq!(records, items);
+
+    let empty = build_cqdb(&[], Flag::NONE);
+    assert_eq!(CQDB::new(&empty).unwrap().records().count(), 0);
+}
+
+#[test]
+fn test_records_empty_with_sections() {
+    let normalized = CQDBBuilder::new()
+        .with_normalizer(AsciiLowercase)
+        .to_vec()
+        .unwrap();
+    let sorted = build_cqdb(&[], Flag::SORTED_INDEX);
+    for buf in [normalized, sorted] {
+        let db = CQDB::new(&buf).unwrap();
+        assert_eq!(db.records().count(), 0);
+        assert_eq!(db.stats().record_bytes, 0);
+    }
+}
+
+#[test]
+fn test_records_corrupt() {
+    let mut buf = build_cqdb(&[("hello", 0), ("world", 1)], Flag::ONEWAY);
+    buf[2076..2080].copy_from_slice(&0xFFFFu32.to_le_bytes());
+    let db = CQDB::new(&buf).unwrap();
+    let mut records = db.records();
+    assert!(matches!(
+        records.next(),
+        Some(Err(Error::CorruptRecord { offset: 2072 }))
+    ));
+    assert!(records.next().is_none());
+}
+
+#[test]
+fn test_iter_all_skips_gaps() {
+    let buf = build_cqdb(
+        &[("zero", 0), ("one", 1), ("five", 5), ("nine", 9)],
+        Flag::NONE,
+    );
+    let db = CQDB::new(&buf).unwrap();
+
+    let iter = db.iter_all();
+    assert_eq!(iter.len(), 4);
+    let items: Vec<_> = iter.map(|r| r.unwrap()).collect();
+    let expected = [(0, "zero"), (1, "one"), (5, "five"), (9, "nine")];
+    assert_eq!(items.len(), expected.len());
+    for ((id, key), (expected_id, expected_key)) in items.into_iter().zip(expected) {
+        assert_eq!(id, expected_id);
+        assert_eq!(key, expected_key);
+    }
+
+    let ids: Vec<_> = db.iter_all().rev().map(|r| r.unwrap().0).collect();
+    assert_eq!(ids, [9, 5, 1, 0]);
+}
+
+#[test]
+fn test_iter_range() {
+    let keys: Vec<(String, u32)> = (0..300)
+        .filter(|i| i % 3 != 0)
+        .map(|i| (format!("key_{}", i), i))
+        .collect();
+    let refs: Vec<(&str, u32)> = keys.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    let buf = build_cqdb(&refs, Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+
+    let mut iter = db.iter_range(100..200);
+    assert_eq!(iter.len(), 67);
+    assert_eq!(iter.next().unwrap().unwrap().0, 100);
+    assert_eq!(iter.next_back().unwrap().unwrap().0, 199);
+    assert_eq!(iter.len(), 65);
+    let (id, key) = iter.nth(10).unwrap().unwrap();
+    assert_eq!(id, 116);
+    assert_eq!(key, "key_116");
+    assert_eq!(iter.len(), 54);
+    assert_eq!(iter.count(), 54);
+
+    // The length is counted on demand from where the iterator is
+    let mut iter = db.iter_range(100..200);
+    iter.nth(5).unwrap().unwrap();
+    iter.next_back().unwrap().unwrap();
+    assert_eq!(iter.len(), 60);
+    assert_eq!(iter.next().unwrap().unwrap().0, 109);
+    assert_eq!(iter.len(), 59);
+    assert_eq!(db.iter_all().collect::<Vec<_>>().len(), 200);
+
+    assert_eq!(db.iter_range(..=1).len(), 1);
+    assert_eq!(db.iter_range(298..).len(), 2);
+    assert_eq!(db.iter_range(1000..2000).len(), 0);
+    assert!(db.iter_range(0..300).nth(1000).is_none());
+}
+
+#[test]
+fn test_iter_range_oneway() {
+    let buf = build_cqdb(&[("a", 0), ("b", 1)], Flag::ONEWAY);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.iter_all().len(), 0);
+    assert!(db.iter_all().next().is_none());
+}
+
+#[test]
+fn test_to_ids_batch() {
+    let keys: Vec<(String, u32)> = (0..1000).map(|i| (format!("key_{}", i), i)).collect();
+    let refs: Vec<(&str, u32)> = keys.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    let buf = build_cqdb(&refs, Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+
+    let queries: Vec<String> = (0..1100).rev().map(|i| format!("key_{}", i)).collect();
+    let mut ids = vec![None; queries.len()];
+    db.to_ids(&queries, &mut ids);
+    for (query, id) in queries.iter().zip(&ids) {
+        assert_eq!(*id, db.to_id(query), "key: {}", query);
+    }
+    assert_eq!(ids.iter().filter(|id| id.is_some()).count(), 1000);
+
+    let mut ids = [None; 3];
+    db.to_ids(&[&b"key_1"[..], b"missing", b"key_999"], &mut ids);
+    assert_eq!(ids, [Some(1), None, Some(999)]);
+
+    let empty = build_cqdb(&[], Flag::NONE);
+    let db = CQDB::new(&empty).unwrap();
+    let mut ids = [Some(0); 2];
+    db.to_ids(&["a", "b"], &mut ids);
+    assert_eq!(ids, [None, None]);
+}
+
+#[test]
+#[should_panic(expected = "same length")]
+fn test_to_ids_length_mismatch() {
+    let buf = build_cqdb(&[("a", 0)], Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+    db.to_ids(&["a", "b"], &mut [None]);
+}
+
+fn sorted_fixture(flag: Flag) -> Vec<u8> {
+    build_cqdb(
+        &[
+            ("w[0]=the", 0),
+            ("w[-1]=cat", 1),
+            ("w[-1]=a", 2),
+            ("w[1]=sat", 3),
+            ("w[-1]=", 4),
+            ("pos=NN", 5),
+            ("w[-1]=cab", 6),
+        ],
+        flag | Flag::SORTED_INDEX,
+    )
+}
+
+#[test]
+fn test_sorted_index_prefix() {
+    for flag in [Flag::NONE, Flag::ONEWAY] {
+        let buf = sorted_fixture(flag);
+        let db = CQDB::new(&buf).unwrap();
+        assert!(db.has_sorted_index());
+
+        let items: Vec<_> = db.prefix(b"w[-1]=").unwrap().map(|r| r.unwrap()).collect();
+        let keys: Vec<_> = items.iter().map(|(key, _)| key.to_str().unwrap()).collect();
+        assert_eq!(keys, ["w[-1]=", "w[-1]=a", "w[-1]=cab", "w[-1]=cat"]);
+        let ids: Vec<_> = items.iter().map(|(_, id)| *id).collect();
+        assert_eq!(ids, [4, 2, 6, 1]);
+
+        assert_eq!(db.prefix("w[-1]=ca").unwrap().len(), 2);
+        assert_eq!(db.prefix("").unwrap().len(), 7);
+        assert_eq!(db.prefix("x").unwrap().len(), 0);
+        assert_eq!(db.prefix("a").unwrap().len(), 0);
+        let last = db.prefix("w[").unwrap().next_back().unwrap().unwrap();
+        assert_eq!(last.0, "w[1]=sat");
+
+        // Regular lookups are unaffected
+        assert_eq!(db.to_id("pos=NN"), Some(5));
+        let report = db.verify();
+        assert!(report.is_ok(), "{}", report);
+    }
+}
+
+#[test]
+fn test_sorted_index_range() {
+    let buf = sorted_fixture(Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+
+    let keys = |iter: cqdb::SortedIter<'_>| -> Vec<String> {
+        iter.map(|r| r.unwrap().0.to_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        keys(db.range("w[-1]=a".."w[-1]=cat").unwrap()),
+        ["w[-1]=a", "w[-1]=cab"]
+    );
+    assert_eq!(
+        keys(db.range("w[-1]=a"..="w[-1]=cat").unwrap()),
+        ["w[-1]=a", "w[-1]=cab", "w[-1]=cat"]
+    );
+    assert_eq!(keys(db.range(.."w").unwrap()), ["pos=NN"]);
+    assert_eq!(keys(db.range("w[1]"..).unwrap()), ["w[1]=sat"]);
+    assert_eq!(db.range::<&str, _>(..).unwrap().len(), 7);
+    assert_eq!(db.range("z".."a").unwrap().len(), 0);
+}
+
+#[test]
+fn test_sorted_index_absent() {
+    let buf = build_cqdb(&[("a", 0)], Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+    assert!(!db.has_sorted_index());
+    assert!(db.prefix("a").is_none());
+    assert!(db.range("a".."b").is_none());
+
+    let buf = build_cqdb(&[], Flag::SORTED_INDEX);
+    let db = CQDB::new(&buf).unwrap();
+    assert!(db.has_sorted_index());
+    assert_eq!(db.prefix("").unwrap().len(), 0);
+}
+
+#[test]
+fn test_cqdb_sys_read_sorted_index() {
+    let buf = sorted_fixture(Flag::NONE);
+    unsafe {
+        let db = cqdb_sys::cqdb_reader(buf.as_ptr() as _, buf.len());
+        assert!(!db.is_null());
+        let key = CString::new("w[-1]=cab").unwrap();
+        assert_eq!(6, cqdb_sys::cqdb_to_id(db, key.as_ptr()));
+        let ptr = cqdb_sys::cqdb_to_string(db, 5);
+        assert!(!ptr.is_null());
+        assert_eq!(CStr::from_ptr(ptr).to_str().unwrap(), "pos=NN");
+        cqdb_sys::cqdb_delete(db);
+    }
+}
+
+#[test]
+fn test_fuzzy_lookup() {
+    let keys = [
+        ("berlin", 0),
+        ("bern", 1),
+        ("berne", 2),
+        ("dublin", 3),
+        ("london", 4),
+        ("lisbon", 5),
+        ("paris", 6),
+        ("parma", 7),
+        ("a", 8),
+        ("ab", 9),
+    ];
+    for flag in [Flag::NONE, Flag::ONEWAY] {
+        let buf = build_cqdb(&keys, flag);
+        let db = CQDB::new(&buf).unwrap();
+
+        let found = db.fuzzy("berlin", 0, 10);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "berlin");
+        assert_eq!((found[0].1, found[0].2), (0, 0));
+
+        let found: Vec<_> = db
+            .fuzzy("bernn", 2, 10)
+            .into_iter()
+            .map(|(key, id, distance)| (key.to_str().unwrap(), id, distance))
+            .collect();
+        assert_eq!(found, [("bern", 1, 1), ("berne", 2, 1), ("berlin", 0, 2)]);
+
+        assert_eq!(db.fuzzy("bernn", 2, 2).len(), 2);
+        assert!(db.fuzzy("tokyo", 1, 10).is_empty());
+        assert_eq!(db.fuzzy("pariz", 1, 10)[0].1, 6);
+
+        // Short queries fall back to a scan over compatible key lengths
+        let found: Vec<_> = db.fuzzy("b", 1, 10).into_iter().map(|r| r.1).collect();
+        assert_eq!(found, [8, 9]);
+        let found: Vec<_> = db.fuzzy("", 1, 10).into_iter().map(|r| r.1).collect();
+        assert_eq!(found, [8]);
+    }
+}
+
+#[test]
+fn test_fuzzy_shared_index() {
+    let buf = build_cqdb(&[("hello", 0), ("help", 1)], Flag::NONE);
+    let db = OwnedCQDB::from_reader(&buf[..]).unwrap();
+    assert_eq!(db.fuzzy("helo", 1, 10).len(), 2);
+    let clone = db.clone();
+    assert_eq!(clone.fuzzy("hellp", 1, 1)[0].0, "hello");
+
+    let empty = build_cqdb(&[], Flag::NONE);
+    assert!(CQDB::new(&empty).unwrap().fuzzy("x", 3, 10).is_empty());
+}
+
+#[test]
+fn test_new_bad_chunk_size() {
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    let size = buf.len() as u32;
+    buf[4..8].copy_from_slice(&(size + 1).to_le_bytes());
+    assert!(matches!(
+        CQDB::new(&buf),
+        Err(Error::BadChunkSize { size: s }) if s == u64::from(size + 1)
+    ));
+    buf[4..8].copy_from_slice(&100u32.to_le_bytes());
+    assert!(matches!(
+        CQDB::new(&buf),
+        Err(Error::BadChunkSize { size: 100 })
+    ));
+}
+
+#[test]
+fn test_new_ignores_trailing_data() {
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    let size = buf.len();
+    buf.extend_from_slice(b"trailing data");
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.to_id("hello"), Some(0));
+    assert_eq!(db.records().count(), 1);
+    assert!(db.verify().is_ok());
+
+    // A table extending past the chunk is rejected even if it fits in the buffer
+    buf[4..8].copy_from_slice(&(size as u32 - 4).to_le_bytes());
+    assert!(CQDB::new(&buf).is_err());
+}
+
+#[test]
+fn test_chunks_embedded() {
+    let mut buf = Cursor::new(Vec::new());
+    buf.get_mut().extend_from_slice(b"file header");
+    buf.set_position(11);
+    let mut writer = CQDBWriter::new(&mut buf).unwrap();
+    writer.put("first", 0).unwrap();
+    writer.put("shared", 1).unwrap();
+    drop(writer);
+    let second = buf.get_ref().len();
+    buf.set_position(second as u64);
+    let mut writer = CQDBWriter::with_flag(&mut buf, Flag::ONEWAY).unwrap();
+    writer.put("second", 0).unwrap();
+    writer.put("shared", 2).unwrap();
+    drop(writer);
+    let buf = buf.into_inner();
+
+    let first = CQDB::at(&buf, 11).unwrap();
+    assert_eq!(first.to_id("first"), Some(0));
+    assert_eq!(first.to_id("shared"), Some(1));
+    assert_eq!(first.to_id("second"), None);
+    assert_eq!(first.to_str(1).unwrap(), "shared");
+    assert!(first.verify().is_ok());
+
+    let dbs: Vec<_> = CQDB::chunks(&buf[11..]).map(|db| db.unwrap()).collect();
+    assert_eq!(dbs.len(), 2);
+    assert_eq!(dbs[1].to_id("second"), Some(0));
+    assert_eq!(dbs[1].to_id("shared"), Some(2));
+    assert_eq!(dbs[1].to_id("first"), None);
+    assert_eq!(dbs[1].records().count(), 2);
+    assert!(dbs[1].verify().is_ok());
+
+    let mut chunks = CQDB::chunks(&buf);
+    assert!(matches!(chunks.next(), Some(Err(Error::BadMagic))));
+    assert!(chunks.next().is_none());
+
+    assert!(matches!(
+        CQDB::at(&buf, buf.len() + 1),
+        Err(Error::TooSmall)
+    ));
+}
+
+#[test]
+fn test_to_id_hashed() {
+    let first = build_cqdb(&[("apple", 0), ("banana", 1)], Flag::NONE);
+    let second = build_cqdb(&[("banana", 7), ("cherry", 8)], Flag::ONEWAY);
+    let first = CQDB::new(&first).unwrap();
+    let second = CQDB::new(&second).unwrap();
+
+    let hash = KeyHash::new("banana");
+    assert_eq!(first.to_id_hashed("banana", hash), Some(1));
+    assert_eq!(second.to_id_hashed("banana", hash), Some(7));
+    assert_eq!(first.to_id_hashed("cherry", KeyHash::new("cherry")), None);
+    // A wrong hash does not find the key
+    assert_eq!(first.to_id_hashed("banana", KeyHash::new("apple")), None);
+
+    for record in first.records() {
+        let record = record.unwrap();
+        assert_eq!(KeyHash::new(record.key).value(), record.hash);
+    }
+}
+
+#[test]
+fn test_candidates() {
+    let buf = build_cqdb(&[("apple", 0), ("banana", 1)], Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+
+    let hash = KeyHash::new("apple");
+    let candidates: Vec<_> = db.candidates(hash).map(|r| r.unwrap()).collect();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].id, 0);
+    assert_eq!(candidates[0].key, "apple");
+    assert_eq!(candidates[0].hash, hash.value());
+    assert_eq!(candidates[0].offset, 2072);
+
+    assert_eq!(db.candidates(KeyHash::new("missing")).count(), 0);
+
+    // Two records with the same key share a hash and are both listed
+    let buf = build_cqdb(&[("dup", 0), ("dup", 1)], Flag::NONE);
+    let db = CQDB::new(&buf).unwrap();
+    let ids: Vec<_> = db
+        .candidates(KeyHash::new("dup"))
+        .map(|r| r.unwrap().id)
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&0) && ids.contains(&1));
+
+    let empty = build_cqdb(&[], Flag::NONE);
+    let db = CQDB::new(&empty).unwrap();
+    assert_eq!(db.candidates(hash).count(), 0);
+}
+
+#[test]
+fn test_stats() {
+    let buf = fs::read("tests/fixtures/test.cqdb").unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.flag(), Flag::NONE);
+    assert!(!db.is_oneway());
+    assert_eq!(db.size() as usize, buf.len());
+    assert_eq!(db.bwd_size(), 100);
+
+    let stats = db.stats();
+    assert_eq!(stats.flag, Flag::NONE);
+    assert_eq!(stats.size as usize, buf.len());
+    assert_eq!(stats.bwd_size, 100);
+    assert_eq!(stats.table_records.len(), 256);
+    assert_eq!(stats.table_records.iter().sum::<u32>(), 100);
+    assert_eq!(stats.occupied, 100);
+    assert_eq!(stats.buckets, 200);
+    assert_eq!(stats.occupancy(), 0.5);
+    assert!(stats.avg_probe_len >= 1.0);
+    assert!(stats.max_probe_len as f64 >= stats.avg_probe_len);
+    assert_eq!(stats.key_lengths.len(), 1);
+    assert_eq!(stats.key_lengths[&8], 100);
+    assert_eq!(stats.header_bytes, 2072);
+    assert_eq!(stats.record_bytes, 100 * (8 + 9));
+    assert_eq!(stats.table_bytes, 200 * 8);
+    assert_eq!(stats.bwd_bytes, 100 * 4);
+    assert_eq!(stats.section_bytes, 0);
+    assert_eq!(
+        stats.header_bytes + stats.record_bytes + stats.table_bytes + stats.bwd_bytes,
+        buf.len()
+    );
+    assert!(stats.to_string().contains("occupied"));
+}
+
+#[test]
+fn test_stats_oneway_sorted() {
+    let buf = build_cqdb(
+        &[("a", 0), ("bb", 1), ("cc", 2)],
+        Flag::ONEWAY | Flag::SORTED_INDEX,
+    );
+    let db = CQDB::new(&buf).unwrap();
+    assert!(db.is_oneway());
+    assert_eq!(db.flag(), Flag::ONEWAY | Flag::SORTED_INDEX);
+
+    let stats = db.stats();
+    assert_eq!(stats.bwd_offset, 0);
+    assert_eq!(stats.bwd_bytes, 0);
+    assert_eq!(stats.occupied, 3);
+    assert_eq!(stats.key_lengths[&1], 1);
+    assert_eq!(stats.key_lengths[&2], 2);
+    // Section header and three record offsets
+    assert_eq!(stats.section_bytes, 8 + 3 * 4);
+
+    let empty = build_cqdb(&[], Flag::NONE);
+    let stats = CQDB::new(&empty).unwrap().stats();
+    assert_eq!(stats.buckets, 0);
+    assert_eq!(stats.occupancy(), 0.0);
+    assert_eq!(stats.avg_probe_len, 0.0);
+}
+
+#[test]
+fn test_lazy_reader() {
+    for blocks in [0, 1, 16] {
+        let file = fs::File::open("tests/fixtures/test.cqdb").unwrap();
+        let mut db = LazyCQDB::new(file).unwrap().with_cache(blocks);
+        assert_eq!(db.num(), 100);
+        for i in 0..100 {
+            let s = format!("{:08}", i);
+            assert_eq!(db.try_to_id(&s).unwrap(), Some(i));
+            assert_eq!(db.try_to_str(i).unwrap().unwrap(), s);
+        }
+        assert_eq!(db.try_to_id("non-existing-key").unwrap(), None);
+        assert_eq!(db.try_to_str(100).unwrap(), None);
+        assert_eq!(db.to_id("00000042"), Some(42));
+        assert_eq!(db.to_str(42).unwrap(), "00000042");
+        assert_eq!(db.to_str(100), None);
+    }
+}
+
+#[test]
+fn test_lazy_reader_large_keys_and_chunks() {
+    let large_key = "x".repeat(10000);
+    let buf = build_cqdb(&[(&large_key, 0), ("small", 1)], Flag::ONEWAY);
+    let mut stream = Cursor::new([b"prefix".as_slice(), &buf].concat());
+    stream.set_position(6);
+    let mut db = LazyCQDB::new(stream).unwrap().with_cache(2);
+    assert_eq!(db.try_to_id(&large_key).unwrap(), Some(0));
+    assert_eq!(db.try_to_id("small").unwrap(), Some(1));
+    assert_eq!(db.try_to_str(0).unwrap(), None);
+    assert_eq!(db.into_inner().get_ref().len(), buf.len() + 6);
+}
+
+#[test]
+fn test_lazy_reader_validation() {
+    assert!(matches!(
+        LazyCQDB::new(Cursor::new(vec![0u8; 100])),
+        Err(Error::TooSmall)
+    ));
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    buf[16..20].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    assert!(matches!(
+        LazyCQDB::new(Cursor::new(&buf)),
+        Err(Error::BackwardLinkOutOfBounds)
+    ));
+
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    buf[2076..2080].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+    let mut db = LazyCQDB::new(Cursor::new(&buf)).unwrap();
+    assert!(matches!(
+        db.try_to_id("hello"),
+        Err(Error::CorruptRecord { offset: 2072 })
+    ));
+    assert!(matches!(
+        db.try_to_str(0),
+        Err(Error::CorruptRecord { offset: 2072 })
+    ));
+    assert_eq!(db.to_id("hello"), None);
+    assert_eq!(db.to_str(0), None);
+}
+
+#[test]
+fn test_buf_sink() {
+    let keys = [("alpha", 0), ("beta", 1), ("gamma", 2)];
+    let expected = build_cqdb(&keys, Flag::NONE);
+
+    let mut buf = Vec::new();
+    let mut writer = CQDBWriter::new(BufSink::new(&mut buf)).unwrap();
+    for &(key, id) in &keys {
+        writer.put(key, id).unwrap();
+    }
+    drop(writer);
+    assert_eq!(buf, expected);
+
+    let mut fixed = vec![0u8; expected.len()];
+    let mut writer = CQDBWriter::new(BufSink::new(fixed.as_mut_slice())).unwrap();
+    for &(key, id) in &keys {
+        writer.put(key, id).unwrap();
+    }
+    drop(writer);
+    assert_eq!(fixed, expected);
+
+    // A chunk appended after existing data
+    let mut buf = b"head".to_vec();
+    let mut writer = CQDBWriter::new(BufSink::append(&mut buf)).unwrap();
+    writer.put("alpha", 0).unwrap();
+    drop(writer);
+    assert_eq!(CQDB::at(&buf, 4).unwrap().to_id("alpha"), Some(0));
+}
+
+#[test]
+fn test_buf_sink_full() {
+    let mut small = [0u8; 100];
+    let mut writer = CQDBWriter::new(BufSink::new(&mut small[..])).unwrap();
+    assert!(matches!(writer.put("alpha", 0), Err(Error::BufferFull)));
+}
+
+#[test]
+fn test_byte_order_round_trip() {
+    for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+        for flag in [Flag::NONE, Flag::ONEWAY, Flag::SORTED_INDEX] {
+            let mut buf = Cursor::new(Vec::new());
+            let mut writer = CQDBWriter::with_byte_order(&mut buf, flag, order).unwrap();
+            for i in 0..100 {
+                writer.put(format!("{:08}", i), i).unwrap();
+            }
+            drop(writer);
+            let buf = buf.into_inner();
+            let marker: &[u8] = match order {
+                ByteOrder::LittleEndian => b"qSDb",
+                ByteOrder::BigEndian => b"bDSq",
+            };
+            assert_eq!(&buf[12..16], marker);
+
+            let db = CQDB::new(&buf).unwrap();
+            assert_eq!(db.byte_order(), order);
+            assert_eq!(db.num(), 100);
+            assert_eq!(db.flag(), flag);
+            assert!(db.verify().is_ok(), "{}", db.verify());
+            for i in 0..100 {
+                let s = format!("{:08}", i);
+                assert_eq!(db.to_id(&s), Some(i));
+                if flag.contains(Flag::ONEWAY) {
+                    assert_eq!(db.to_str(i), None);
+                } else {
+                    assert_eq!(db.to_str(i).unwrap(), s);
+                }
+            }
+            assert_eq!(db.records().count(), 100);
+            if flag.contains(Flag::SORTED_INDEX) {
+                let keys: Vec<_> = db
+                    .prefix("0000009")
+                    .unwrap()
+                    .map(|r| r.unwrap().1)
+                    .collect();
+                assert_eq!(keys, (90..100).collect::<Vec<_>>());
+            }
+
+            let mut lazy = LazyCQDB::new(Cursor::new(&buf)).unwrap();
+            assert_eq!(lazy.try_to_id("00000042").unwrap(), Some(42));
+        }
+    }
+}
+
+#[test]
+fn test_byte_order_mismatch() {
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    buf[12..16].copy_from_slice(b"DbqS");
+    assert!(matches!(CQDB::new(&buf), Err(Error::ByteOrder)));
+}
+
+#[test]
+fn test_byte_order_big_endian_fixture() {
+    // Written by tests/fixtures/gen_fixture.py, not by this crate
+    let buf = fs::read("tests/fixtures/test_be.cqdb").unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.byte_order(), ByteOrder::BigEndian);
+    assert_eq!(db.num(), 100);
+    assert!(db.verify().is_ok(), "{}", db.verify());
+    for i in 0..100 {
+        let s = format!("{:08}", i).repeat(1 + i as usize % 3);
+        assert_eq!(db.to_id(&s), Some(i));
+        assert_eq!(db.to_str(i).unwrap(), s);
+    }
+
+    // The crate writes the same bytes for the same associations
+    let mut out = Cursor::new(Vec::new());
+    let mut writer =
+        CQDBWriter::with_byte_order(&mut out, Flag::NONE, ByteOrder::BigEndian).unwrap();
+    for i in 0..100 {
+        writer
+            .put(format!("{:08}", i).repeat(1 + i as usize % 3), i)
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    assert_eq!(out.into_inner(), buf);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    use cqdb::KeyFormat;
+
+    let buf = build_cqdb(
+        &[("alpha", 0), ("back\\slash", 1), ("gamma", 2)],
+        Flag::NONE,
+    );
+    let db = CQDB::new(&buf).unwrap();
+
+    let json = serde_json::to_string(&db).unwrap();
+    assert_eq!(json, r#"{"alpha":0,"back\\slash":1,"gamma":2}"#);
+    let json = serde_json::to_string(&db.as_pairs(KeyFormat::Auto)).unwrap();
+    assert_eq!(json, r#"[[0,"alpha"],[1,"back\\slash"],[2,"gamma"]]"#);
+
+    for json in [
+        r#"{"alpha":0,"back\\slash":1,"gamma":2}"#,
+        r#"[[0,"alpha"],[1,"back\\slash"],[2,[103,97,109,109,97]]]"#,
+    ] {
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = CQDBWriter::new(&mut out).unwrap();
+        let mut de = serde_json::Deserializer::from_str(json);
+        assert_eq!(
+            writer.put_deserialized(&mut de, KeyFormat::Auto).unwrap(),
+            3
+        );
+        drop(writer);
+        assert_eq!(out.into_inner(), buf);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_empty_with_sections() {
+    let normalized = CQDBBuilder::new()
+        .with_normalizer(AsciiLowercase)
+        .to_vec()
+        .unwrap();
+    let sorted = build_cqdb(&[], Flag::SORTED_INDEX);
+    for buf in [normalized, sorted] {
+        let db = CQDB::new(&buf).unwrap();
+        assert_eq!(serde_json::to_string(&db).unwrap(), "{}");
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_non_utf8_keys() {
+    use cqdb::KeyFormat;
+
+    let keys: [&[u8]; 3] = [b"plain", b"\\xff", b"bad\xff\xfe"];
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap();
+    for (id, key) in keys.iter().enumerate() {
+        writer.put(key, id as u32).unwrap();
+    }
+    drop(writer);
+    let buf = buf.into_inner();
+    let db = CQDB::new(&buf).unwrap();
+
+    // JSON map keys must be strings
+    assert!(serde_json::to_string(&db).is_err());
+
+    let json = serde_json::to_string(&db.as_map(KeyFormat::Escaped)).unwrap();
+    assert_eq!(json, r#"{"plain":0,"\\\\xff":1,"bad\\xff\\xfe":2}"#);
+    let bytes = serde_json::to_string(&db.as_pairs(KeyFormat::Bytes)).unwrap();
+    assert!(bytes.starts_with("[[0,[112,108,97,105,110]],"));
+
+    for (json, format) in [(json, KeyFormat::Escaped), (bytes, KeyFormat::Bytes)] {
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = CQDBWriter::new(&mut out).unwrap();
+        let mut de = serde_json::Deserializer::from_str(&json);
+        writer.put_deserialized(&mut de, format).unwrap();
+        drop(writer);
+        assert_eq!(out.into_inner(), buf);
+    }
+
+    let mut writer = CQDBWriter::new(Cursor::new(Vec::new())).unwrap();
+    let mut de = serde_json::Deserializer::from_str(r#"{"bad\\q":0}"#);
+    assert!(
+        writer
+            .put_deserialized(&mut de, KeyFormat::Escaped)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_full_table_probe_terminates() {
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    let (table, pos) = first_bucket(&buf);
+    let offset = read_u32(&buf, 24 + table * 8) as usize;
+    // Fill the vacant bucket so the table has no empty slot left
+    let vacant = if pos == offset { offset + 8 } else { offset };
+    buf[vacant..vacant + 4].copy_from_slice(&0xDEADBEEFu32.to_le_bytes());
+    buf[vacant + 4..vacant + 8].copy_from_slice(&2072u32.to_le_bytes());
+    let missing = (0..)
+        .map(|i| format!("missing{}", i))
+        .find(|key| KeyHash::new(key).table() == table)
+        .unwrap();
+
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.to_id("hello"), Some(0));
+    assert_eq!(db.to_id(&missing), None);
+    assert_eq!(db.candidates(KeyHash::new(&missing)).count(), 0);
+    let mut ids = [Some(0)];
+    db.to_ids(&[&missing], &mut ids);
+    assert_eq!(ids, [None]);
+    let mut lazy = LazyCQDB::new(Cursor::new(&buf)).unwrap();
+    assert_eq!(lazy.try_to_id(&missing).unwrap(), None);
+}
+
+/// Exercise every reader method on a possibly corrupt buffer
+fn read_everything(buf: &[u8]) {
+    let Ok(db) = CQDB::new(buf) else {
+        return;
+    };
+    let _ = db.stats();
+    let _ = db.verify();
+    let _ = db.fuzzy("00000042", 1, 3);
+    for key in ["00000000", "00000042", "w[-1]=cat", "missing"] {
+        let _ = db.try_to_id(key);
+        let _ = db.candidates(KeyHash::new(key)).count();
+    }
+    let mut ids = [None; 3];
+    db.to_ids(&["00000001", "w[0]=the", ""], &mut ids);
+    for id in 0..db.num().saturating_add(2).min(1000) {
+        let _ = db.try_to_str(id);
+    }
+    let _ = db.iter().take(1000).count();
+    let _ = db.iter_all().rev().take(1000).count();
+    let _ = db.records().take(1000).count();
+    if let Some(iter) = db.prefix("w[-1]") {
+        let _ = iter.count();
+    }
+    if let Some(iter) = db.range::<&str, _>(..) {
+        let _ = iter.rev().count();
+    }
+    if let Ok(mut lazy) = LazyCQDB::new(Cursor::new(buf)) {
+        let _ = lazy.try_to_id("00000042");
+        let _ = lazy.try_to_str(42);
+    }
+}
+
+#[test]
+fn test_corrupt_input_never_panics() {
+    let fixtures = [
+        fs::read("tests/fixtures/test.cqdb").unwrap(),
+        sorted_fixture(Flag::NONE),
+    ];
+    // xorshift, deterministic so failures are reproducible
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for fixture in &fixtures {
+        for _ in 0..300 {
+            let mut buf = fixture.clone();
+            for _ in 0..1 + next() % 4 {
+                // Favour the header, table references and the start of the data
+                let limit = if next() % 2 == 0 { 2200 } else { buf.len() };
+                let pos = (next() as usize) % limit.min(buf.len());
+                buf[pos] = match next() % 4 {
+                    0 => 0,
+                    1 => 0xFF,
+                    _ => next() as u8,
+                };
+            }
+            read_everything(&buf);
+            let cut = (next() as usize) % buf.len();
+            read_everything(&buf[..cut]);
+        }
+    }
+}
+
+#[test]
+fn test_normalizer_ascii_lowercase() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::with_flag(&mut buf, Flag::SORTED_INDEX)
+        .unwrap()
+        .with_normalizer(AsciiLowercase);
+    writer.put("Hello", 0).unwrap();
+    writer.put("WORLD", 1).unwrap();
+    drop(writer);
+    let buf = buf.into_inner();
+
+    let db = CQDB::new(&buf).unwrap();
+    assert!(db.flag().contains(Flag::NORMALIZED));
+    assert_eq!(db.normalizer().unwrap().name(), "ascii-lowercase");
+    assert!(db.verify().is_ok());
+    for key in ["hello", "Hello", "HELLO"] {
+        assert_eq!(db.to_id(key), Some(0));
+    }
+    assert_eq!(db.to_str(1).unwrap(), "world");
+    assert_eq!(db.normalize(b"WoRlD"), &b"world"[..]);
+    let mut ids = [None; 2];
+    db.to_ids(&["hELLo", "World"], &mut ids);
+    assert_eq!(ids, [Some(0), Some(1)]);
+    let keys: Vec<_> = db.prefix("W").unwrap().map(|r| r.unwrap().1).collect();
+    assert_eq!(keys, [1]);
+
+    let mut lazy = LazyCQDB::new(Cursor::new(&buf)).unwrap();
+    assert_eq!(lazy.try_to_id("HeLLo").unwrap(), Some(0));
+}
+
+#[test]
+fn test_normalizer_missing_section() {
+    // Flagged as normalized without a normalizer section
+    let mut buf = build_cqdb(&[("hello", 0)], Flag::NONE);
+    buf[8..12].copy_from_slice(&Flag::NORMALIZED.bits().to_le_bytes());
+    assert!(matches!(CQDB::new(&buf), Err(Error::MissingNormalizer)));
+    assert!(matches!(
+        LazyCQDB::new(Cursor::new(&buf)),
+        Err(Error::MissingNormalizer)
+    ));
+
+    // Truncated normalizer section
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf)
+        .unwrap()
+        .with_normalizer(AsciiLowercase);
+    writer.put("Hello", 0).unwrap();
+    writer.finish().unwrap();
+    let mut buf = buf.into_inner();
+    buf.pop();
+    let size = buf.len() as u32;
+    buf[4..8].copy_from_slice(&size.to_le_bytes());
+    assert!(matches!(CQDB::new(&buf), Err(Error::MissingNormalizer)));
+    assert!(matches!(
+        LazyCQDB::new(Cursor::new(&buf)),
+        Err(Error::MissingNormalizer)
+    ));
+}
+
+#[test]
+fn test_normalized_flag_needs_normalizer() {
+    let flag = Flag::NORMALIZED | Flag::SORTED_INDEX;
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::with_flag(&mut buf, flag).unwrap();
+    writer.put("Hello", 0).unwrap();
+    writer.finish().unwrap();
+    let mut builder = CQDBBuilder::new().with_flag(flag);
+    builder.intern("Hello");
+    let built = builder.to_vec().unwrap();
+    let streamed = {
+        let mut writer = CQDBStreamWriter::with_flag(Vec::new(), flag);
+        writer.put("Hello", 0).unwrap();
+        writer.finish().unwrap()
+    };
+    for buf in [buf.into_inner(), built, streamed] {
+        let db = CQDB::new(&buf).unwrap();
+        assert_eq!(db.flag(), Flag::SORTED_INDEX);
+        assert_eq!(db.to_id("Hello"), Some(0));
+        assert_eq!(db.to_id("hello"), None);
+    }
+}
+
+struct Trim;
+
+impl cqdb::KeyNormalizer for Trim {
+    fn name(&self) -> &str {
+        "trim"
+    }
+
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        Cow::Borrowed(key.trim_ascii())
+    }
+}
+
+#[test]
+fn test_normalizer_custom() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap().with_normalizer(Trim);
+    writer.put("  padded ", 0).unwrap();
+    drop(writer);
+    let buf = buf.into_inner();
+
+    match CQDB::new(&buf) {
+        Err(Error::UnknownNormalizer { name }) => assert_eq!(name, "trim"),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(
+        LazyCQDB::new(Cursor::new(&buf)),
+        Err(Error::UnknownNormalizer { .. })
+    ));
+    let db = CQDB::from_storage_with_normalizer(&buf[..], Trim).unwrap();
+    assert_eq!(db.to_id("padded  "), Some(0));
+    assert_eq!(db.to_str(0).unwrap(), "padded");
+    let mut lazy = LazyCQDB::new_with_normalizer(Cursor::new(&buf), Trim).unwrap();
+    assert_eq!(lazy.try_to_id(" padded").unwrap(), Some(0));
+}
+
+#[test]
+#[should_panic(expected = "before putting keys")]
+fn test_normalizer_after_put() {
+    let mut writer = CQDBWriter::new(Cursor::new(Vec::new())).unwrap();
+    writer.put("key", 0).unwrap();
+    let _ = writer.with_normalizer(AsciiLowercase);
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_normalizer_unicode() {
+    use cqdb::{CaseFold, KeyNormalizer, Nfc, Nfkc};
+
+    fn build<N: KeyNormalizer + 'static>(normalizer: N, keys: &[&str]) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        let mut writer = CQDBWriter::new(&mut buf)
+            .unwrap()
+            .with_normalizer(normalizer);
+        for (id, key) in keys.iter().enumerate() {
+            writer.put(key, id as u32).unwrap();
+        }
+        drop(writer);
+        buf.into_inner()
+    }
+
+    let buf = build(CaseFold, &["Straße", "ΣΊΣΥΦΟΣ"]);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.normalizer().unwrap().name(), "case-fold");
+    assert_eq!(db.to_id("STRASSE"), Some(0));
+    assert_eq!(db.to_id("σίσυφος"), Some(1));
+
+    // "é" precomposed and as "e" + combining acute accent
+    let buf = build(Nfc, &["caf\u{e9}"]);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.to_id("cafe\u{301}"), Some(0));
+    assert_eq!(db.to_str(0).unwrap(), "caf\u{e9}");
+
+    let buf = build(Nfkc, &["\u{fb01}le"]);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.to_id("file"), Some(0));
+    assert_eq!(db.to_id(b"fi\xffle"), None);
+}
+
+fn build_with_duplicates(policy: DuplicatePolicy, pairs: &[(&str, u32)]) -> Result<Vec<u8>, Error> {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap().with_duplicates(policy);
+    for &(key, id) in pairs {
+        writer.put(key, id)?;
+    }
+    drop(writer);
+    Ok(buf.into_inner())
+}
+
+#[test]
+fn test_duplicates_error() {
+    let pairs = [("a", 0), ("b", 1)];
+    match build_with_duplicates(DuplicatePolicy::Error, &[pairs[0], pairs[1], ("a", 2)]) {
+        Err(Error::DuplicateKey { key }) => assert_eq!(key, "a"),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(
+        build_with_duplicates(DuplicatePolicy::Error, &[pairs[0], pairs[1], ("c", 1)]),
+        Err(Error::DuplicateId { id: 1 })
+    ));
+
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf)
+        .unwrap()
+        .with_duplicates(DuplicatePolicy::Error);
+    writer.put("a", 0).unwrap();
+    assert!(writer.put("a", 1).is_err());
+    // A rejected association leaves the writer usable
+    writer.put("b", 1).unwrap();
+    drop(writer);
+    let buf = buf.into_inner();
+    assert_eq!(
+        buf,
+        build_with_duplicates(DuplicatePolicy::Allow, &pairs).unwrap()
+    );
+}
+
+#[test]
+fn test_duplicates_keep_first() {
+    let buf = build_with_duplicates(
+        DuplicatePolicy::KeepFirst,
+        &[("a", 0), ("b", 1), ("a", 2), ("c", 1), ("d", 3)],
+    )
+    .unwrap();
+    let expected =
+        build_with_duplicates(DuplicatePolicy::Allow, &[("a", 0), ("b", 1), ("d", 3)]).unwrap();
+    assert_eq!(buf, expected);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.num(), 3);
+    assert_eq!(db.to_id("a"), Some(0));
+    assert_eq!(db.to_id("c"), None);
+}
+
+#[test]
+fn test_duplicates_keep_last() {
+    let buf = build_with_duplicates(
+        DuplicatePolicy::KeepLast,
+        &[("a", 0), ("b", 1), ("c", 2), ("a", 3), ("d", 1), ("a", 4)],
+    )
+    .unwrap();
+    // "b" is replaced by "d" for id 1, "a" keeps its last id
+    let expected =
+        build_with_duplicates(DuplicatePolicy::Allow, &[("c", 2), ("d", 1), ("a", 4)]).unwrap();
+    assert_eq!(buf, expected);
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.num(), 3);
+    assert_eq!(db.to_id("a"), Some(4));
+    assert_eq!(db.to_id("b"), None);
+    assert_eq!(db.to_str(1).unwrap(), "d");
+    assert_eq!(db.to_str(0), None);
+    assert!(db.verify().is_ok());
+}
+
+#[test]
+fn test_duplicates_allow() {
+    let buf =
+        build_with_duplicates(DuplicatePolicy::Allow, &[("a", 0), ("a", 1), ("b", 1)]).unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.num(), 3);
+    assert_eq!(db.to_id("a"), Some(0));
+    assert_eq!(db.to_str(1).unwrap(), "b");
+}
+
+#[test]
+fn test_duplicates_normalized() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf)
+        .unwrap()
+        .with_normalizer(AsciiLowercase)
+        .with_duplicates(DuplicatePolicy::Error);
+    writer.put("Key", 0).unwrap();
+    match writer.put("KEY", 1) {
+        Err(Error::DuplicateKey { key }) => assert_eq!(key, "key"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+#[should_panic(expected = "before putting keys")]
+fn test_duplicates_after_put() {
+    let mut writer = CQDBWriter::new(Cursor::new(Vec::new())).unwrap();
+    writer.put("key", 0).unwrap();
+    let _ = writer.with_duplicates(DuplicatePolicy::KeepFirst);
+}
+
+#[test]
+fn test_writer_finish() {
+    let mut writer = CQDBWriter::new(Cursor::new(Vec::new())).unwrap();
+    writer.put("foo", 0).unwrap();
+    writer.put("bar", 1).unwrap();
+    let cursor = writer.finish().unwrap();
+    assert_eq!(cursor.position(), cursor.get_ref().len() as u64);
+
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap();
+    writer.put("foo", 0).unwrap();
+    writer.put("bar", 1).unwrap();
+    drop(writer);
+    assert_eq!(cursor.into_inner(), buf.into_inner());
+}
+
+#[test]
+fn test_writer_finish_error_rewinds() {
+    // Room for the header and records, but not for the hash tables
+    let mut buf = vec![0u8; 3300];
+    let mut cursor = Cursor::new(&mut buf[..]);
+    cursor.set_position(16);
+    let mut writer = CQDBWriter::new(&mut cursor).unwrap();
+    for id in 0..10 {
+        writer.put(format!("{:0100}", id), id).unwrap();
+    }
+    assert!(matches!(writer.finish(), Err(Error::Io(_))));
+    assert_eq!(cursor.position(), 16);
+
+    let mut buf = [0u8; 2048];
+    let mut writer = CQDBWriter::new(BufSink::new(&mut buf[..])).unwrap();
+    assert!(matches!(writer.put("key", 0), Err(Error::BufferFull)));
+    assert!(matches!(writer.finish(), Err(Error::BufferFull)));
+}
+
+#[test]
+fn test_stream_writer_matches_writer() {
+    let pairs: Vec<(String, u32)> = (0..1000)
+        .map(|i| (format!("key{}", i % 900), (i * 7) % 1000))
+        .collect();
+    for (flag, order) in [
+        (Flag::NONE, ByteOrder::LittleEndian),
+        (Flag::ONEWAY, ByteOrder::LittleEndian),
+        (Flag::SORTED_INDEX, ByteOrder::BigEndian),
+    ] {
+        let mut buf = Cursor::new(Vec::new());
+        let mut writer = CQDBWriter::with_byte_order(&mut buf, flag, order)
+            .unwrap()
+            .with_normalizer(AsciiLowercase)
+            .with_duplicates(DuplicatePolicy::KeepLast);
+        let mut stream = CQDBStreamWriter::with_byte_order(Vec::new(), flag, order)
+            .with_normalizer(AsciiLowercase)
+            .with_duplicates(DuplicatePolicy::KeepLast);
+        for (key, id) in &pairs {
+            writer.put(key, *id).unwrap();
+            stream.put(key, *id).unwrap();
+        }
+        writer.finish().unwrap();
+        let streamed = stream.finish().unwrap();
+        assert_eq!(streamed, buf.into_inner());
+        assert!(CQDB::new(&streamed).unwrap().verify().is_ok());
+    }
+}
+
+#[test]
+fn test_stream_writer_drop() {
+    let mut out = Vec::new();
+    let mut stream = CQDBStreamWriter::new(&mut out);
+    stream.put("foo", 0).unwrap();
+    drop(stream);
+    let db = CQDB::new(&out).unwrap();
+    assert_eq!(db.to_id("foo"), Some(0));
+    assert_eq!(db.to_str(0).unwrap(), "foo");
+}
+
+#[test]
+fn test_builder_intern() {
+    let mut builder = CQDBBuilder::new();
+    assert!(builder.is_empty());
+    assert_eq!(builder.intern("the"), 0);
+    assert_eq!(builder.intern("quick"), 1);
+    assert_eq!(builder.intern("the"), 0);
+    builder.extend(["brown", "quick", "fox"]);
+    assert_eq!(builder.len(), 4);
+    assert_eq!(builder.get("fox"), Some(3));
+    assert_eq!(builder.get("dog"), None);
+
+    let buf = builder.to_vec().unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.num(), 4);
+    for (id, key) in ["the", "quick", "brown", "fox"].iter().enumerate() {
+        assert_eq!(db.to_id(key), Some(id as u32));
+        assert_eq!(db.to_str(id as u32).unwrap(), *key);
+    }
+
+    // Same bytes as putting the keys in id order
+    let mut expected = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut expected).unwrap();
+    for (id, key) in ["the", "quick", "brown", "fox"].iter().enumerate() {
+        writer.put(key, id as u32).unwrap();
+    }
+    writer.finish().unwrap();
+    assert_eq!(buf, expected.into_inner());
+    let cursor = builder.write_to(Cursor::new(Vec::new())).unwrap();
+    assert_eq!(buf, cursor.into_inner());
+}
+
+#[test]
+fn test_builder_options() {
+    let builder: CQDBBuilder = "a b A c B".split(' ').collect();
+    assert_eq!(builder.len(), 5);
+
+    let mut builder = CQDBBuilder::new()
+        .with_flag(Flag::SORTED_INDEX)
+        .with_byte_order(ByteOrder::BigEndian)
+        .with_normalizer(AsciiLowercase);
+    builder.extend("a b A c B".split(' '));
+    assert_eq!(builder.len(), 3);
+    assert_eq!(builder.get("C"), Some(2));
+    let buf = builder.to_vec().unwrap();
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.byte_order(), ByteOrder::BigEndian);
+    assert_eq!(db.to_id("B"), Some(1));
+    let keys: Vec<_> = db.prefix("").unwrap().map(|r| r.unwrap().1).collect();
+    assert_eq!(keys, [0, 1, 2]);
+}
+
+/// Normalizer that is not idempotent
+struct Prefix;
+
+impl cqdb::KeyNormalizer for Prefix {
+    fn name(&self) -> &str {
+        "prefix"
+    }
+
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        Cow::Owned([b"x", key].concat())
+    }
+}
+
+#[test]
+fn test_builder_normalizes_once() {
+    let mut builder = CQDBBuilder::new().with_normalizer(Prefix);
+    assert_eq!(builder.intern("a"), 0);
+    assert_eq!(builder.intern("b"), 1);
+    let buf = builder.to_vec().unwrap();
+    let db = CQDB::from_storage_with_normalizer(&buf[..], Prefix).unwrap();
+    assert_eq!(db.to_id("a"), Some(0));
+    assert_eq!(db.to_str(1).unwrap(), "xb");
+}
+
+#[test]
+fn test_writer_id_limits() {
+    // Header, an empty key record and its hash table take 2097 bytes, leaving
+    // room for the backward links of ids up to 1073741298
+    let mut writer = CQDBWriter::new(Cursor::new(Vec::new())).unwrap();
+    assert!(matches!(
+        writer.put("", 1073741299),
+        Err(Error::TooLarge { size: 4294967297 })
+    ));
+    assert!(matches!(
+        writer.put("", u32::MAX),
+        Err(Error::IdTooLarge { id: u32::MAX })
+    ));
+
+    // Nothing is written for a rejected record
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf).unwrap();
+    writer.put("a", 0).unwrap();
+    assert!(matches!(
+        writer.put("b", u32::MAX),
+        Err(Error::IdTooLarge { .. })
+    ));
+    writer.put("c", 1).unwrap();
+    writer.finish().unwrap();
+    let buf = buf.into_inner();
+    let db = CQDB::new(&buf).unwrap();
+    assert_eq!(db.num(), 2);
+    assert_eq!(db.to_id("b"), None);
+    assert_eq!(db.to_str(1).unwrap(), "c");
+    assert!(db.verify().is_ok());
+
+    // Without backward links every id fits
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::with_flag(&mut buf, Flag::ONEWAY).unwrap();
+    writer.put("max", u32::MAX).unwrap();
+    writer.finish().unwrap();
+    let buf = buf.into_inner();
+    assert_eq!(CQDB::new(&buf).unwrap().to_id("max"), Some(u32::MAX));
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+#[ignore = "allocates a 4 GiB key"]
+fn test_writer_key_limits() {
+    // Never touched: the size checks come before hashing and writing
+    let huge = vec![0u8; u32::MAX as usize];
+    let mut writer = CQDBWriter::with_flag(Cursor::new(Vec::new()), Flag::ONEWAY).unwrap();
+    assert!(matches!(
+        writer.put(&huge, 0),
+        Err(Error::KeyTooLong { len }) if len == u32::MAX as usize
+    ));
+    // Header, record header, NUL byte and hash table take 2097 bytes
+    let key = &huge[..u32::MAX as usize - 2096];
+    assert!(matches!(
+        writer.put(key, 0),
+        Err(Error::TooLarge { size: 4294967296 })
+    ));
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::with_flag(&mut buf, Flag::ONEWAY)
+        .unwrap()
+        .with_duplicates(DuplicatePolicy::Error);
+    assert!(writer.put(key, 0).is_err());
+    // The rejected key is not recorded as a duplicate
+    writer.put("key", 0).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(CQDB::new(buf.get_ref()).unwrap().num(), 1);
+}
+
+/// A sink over a `Vec<u8>` standing at `base` in a larger stream
+struct OffsetSink {
+    base: u64,
+    buf: BufSink<Vec<u8>>,
+}
+
+impl cqdb::Sink for OffsetSink {
+    fn position(&mut self) -> Result<u64, Error> {
+        Ok(self.base + self.buf.position()?)
+    }
+
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error> {
+        self.buf.seek_to(pos - self.base)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.buf.write_all(buf)
+    }
+}
+
+#[test]
+fn test_writer_begin_past_4gib() {
+    use cqdb::Sink;
+
+    let sink = OffsetSink {
+        base: 5 << 30,
+        buf: BufSink::default(),
+    };
+    let mut writer = CQDBWriter::new(sink).unwrap();
+    writer.put("foo", 0).unwrap();
+    writer.put("bar", 1).unwrap();
+    let mut sink = writer.finish().unwrap();
+    assert_eq!(
+        sink.position().unwrap(),
+        (5 << 30) + sink.buf.get_ref().len() as u64
+    );
+
+    let mut expected = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut expected).unwrap();
+    writer.put("foo", 0).unwrap();
+    writer.put("bar", 1).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(sink.buf.into_inner(), expected.into_inner());
+}
+
+#[test]
+fn test_wide_format_round_trip() {
+    for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+        for flag in [Flag::NONE, Flag::ONEWAY, Flag::SORTED_INDEX] {
+            let mut buf = Cursor::new(Vec::new());
+            let mut writer = CQDBWriter::with_byte_order(&mut buf, flag, order)
+                .unwrap()
+                .with_format(Format::Wide)
+                .unwrap()
+                .with_normalizer(AsciiLowercase);
+            for i in 0..100 {
+                writer.put(format!("KEY{:05}", i), i).unwrap();
+            }
+            writer.finish().unwrap();
+            let buf = buf.into_inner();
+            assert_eq!(&buf[0..4], b"CQ64");
+
+            let db = CQDB::new(&buf).unwrap();
+            assert_eq!(db.format(), Format::Wide);
+            assert_eq!(db.size(), buf.len() as u64);
+            assert_eq!(db.num(), 100);
+            assert!(db.verify().is_ok(), "{}", db.verify());
+            for i in 0..100 {
+                let s = format!("key{:05}", i);
+                assert_eq!(db.to_id(s.to_uppercase()), Some(i));
+                if flag.contains(Flag::ONEWAY) {
+                    assert_eq!(db.to_str(i), None);
+                } else {
+                    assert_eq!(db.to_str(i).unwrap(), s);
+                }
+            }
+            let records: Vec<_> = db.records().map(|r| r.unwrap()).collect();
+            assert_eq!(records.len(), 100);
+            assert_eq!(records[0].offset, 3104);
+            if flag.contains(Flag::SORTED_INDEX) {
+                let keys: Vec<_> = db
+                    .prefix("key0009")
+                    .unwrap()
+                    .map(|r| r.unwrap().1)
+                    .collect();
+                assert_eq!(keys, (90..100).collect::<Vec<_>>());
+            }
+            let stats = db.stats();
+            assert_eq!(stats.format, Format::Wide);
+            assert_eq!(stats.header_bytes, 3104);
+            assert_eq!(stats.table_bytes, 200 * 12);
+
+            let mut lazy = LazyCQDB::new(Cursor::new(&buf)).unwrap().with_cache(1);
+            assert_eq!(lazy.try_to_id("KEY00042").unwrap(), Some(42));
+            if !flag.contains(Flag::ONEWAY) {
+                assert_eq!(lazy.try_to_str(42).unwrap().unwrap(), "key00042");
+            }
+        }
+    }
+
+    let standard = build_cqdb(&[("hello", 0)], Flag::NONE);
+    assert_eq!(CQDB::new(&standard).unwrap().format(), Format::Standard);
+}
+
+#[test]
+fn test_wide_format_writers() {
+    let keys = ["foo", "bar", "baz"];
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = CQDBWriter::new(&mut buf)
+        .unwrap()
+        .with_format(Format::Wide)
+        .unwrap();
+    let mut stream = CQDBStreamWriter::new(Vec::new()).with_format(Format::Wide);
+    for (id, key) in keys.iter().enumerate() {
+        writer.put(key, id as u32).unwrap();
+        stream.put(key, id as u32).unwrap();
+    }
+    writer.finish().unwrap();
+    let buf = buf.into_inner();
+    assert_eq!(stream.finish().unwrap(), buf);
+    let builder: CQDBBuilder = keys.into_iter().collect();
+    assert_eq!(builder.with_format(Format::Wide).to_vec().unwrap(), buf);
+
+    // Small databases are picked the standard format
+    let builder: CQDBBuilder = keys.into_iter().collect();
+    assert_eq!(&builder.to_vec().unwrap()[0..4], b"CQDB");
+    let mut stream = CQDBStreamWriter::new(Vec::new());
+    stream.put("foo", 0).unwrap();
+    assert_eq!(&stream.finish().unwrap()[0..4], b"CQDB");
+
+    // Offsets fit, but the backward link array size does not
+    let mut writer = CQDBWriter::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_format(Format::Wide)
+        .unwrap();
+    assert!(matches!(
+        writer.put("max", u32::MAX),
+        Err(Error::IdTooLarge { id: u32::MAX })
+    ));
+}